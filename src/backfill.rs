@@ -0,0 +1,81 @@
+use anyhow::{bail, Context, Result};
+use ethers::prelude::*;
+
+use crate::blocks::{find_block_at, get_latest_snapshot_block};
+use crate::error::NumbaError;
+use crate::store::SnapshotStore;
+use crate::types::{MergeStats, VaultIdentifier};
+use crate::utils::date_time;
+use crate::vaults::{get_holdings, is_deployed, VaultAdapter};
+
+/// Rebuild the holdings of every vault at each `interval_sec` step between `start` and `end`
+/// and merge them into the store. Steps before a vault was deployed are skipped. Reading state
+/// at past blocks requires an archive node.
+pub async fn backfill(
+    provider: &Provider<Http>,
+    store: &dyn SnapshotStore,
+    holder_address: &str,
+    vaults: &[Box<dyn VaultAdapter>],
+    start: u64,
+    end: Option<u64>,
+    interval_sec: u64,
+) -> Result<Vec<(String, MergeStats)>> {
+    let latest = get_latest_snapshot_block(provider).await?;
+    let end = end.unwrap_or(latest.timestamp).min(latest.timestamp);
+    if start > end {
+        bail!(
+            "The backfill starts at {} after it ends at {}",
+            format_timestamp(start),
+            format_timestamp(end)
+        );
+    }
+
+    let ids: Vec<String> = vaults
+        .iter()
+        .map(|vault| VaultIdentifier::new(holder_address, vault.name()).id())
+        .collect();
+    let mut results: Vec<(String, MergeStats)> = vaults
+        .iter()
+        .map(|vault| (vault.name().to_owned(), MergeStats::default()))
+        .collect();
+
+    let mut timestamp = start;
+    while timestamp <= end {
+        let block = find_block_at(provider, timestamp, &latest).await?;
+        let time = date_time(block.timestamp).ok_or_else(|| {
+            NumbaError::UnexpectedResponse(format!(
+                "Block {} has an invalid timestamp {}",
+                block.number, block.timestamp
+            ))
+        })?;
+        println!("Block {} ({})", block.number, time);
+
+        for ((vault, id), (_, stats)) in vaults.iter().zip(&ids).zip(results.iter_mut()) {
+            if !is_deployed(provider, vault.as_ref(), &block).await? {
+                println!("{} is not deployed yet, skipping", vault.name());
+                stats.skipped += 1;
+                continue;
+            }
+            let holdings = get_holdings(provider, vault.as_ref(), holder_address, &block)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Can't read {} at block {} (backfilling requires an archive node)",
+                        vault.name(),
+                        block.number
+                    )
+                })?;
+            let step_stats = store.merge_entries(id, &[holdings])?;
+            stats.added += step_stats.added;
+            stats.skipped += step_stats.skipped;
+        }
+
+        timestamp += interval_sec;
+    }
+
+    Ok(results)
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    date_time(timestamp).map_or_else(|| timestamp.to_string(), |time| time.to_string())
+}
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use ethers::prelude::*;
+
+use crate::error::NumbaError;
+use crate::types::SnapshotBlock;
+use crate::utils::u256_to_u64;
+
+/// Fetch the number and timestamp of the given block
+pub async fn get_snapshot_block(provider: &Provider<Http>, number: U64) -> Result<SnapshotBlock> {
+    let block = provider
+        .get_block(number)
+        .await
+        .map_err(NumbaError::from)?
+        .ok_or_else(|| NumbaError::Rpc(format!("Block {} not found", number)))?;
+
+    Ok(SnapshotBlock {
+        number: number.as_u64(),
+        timestamp: u256_to_u64(block.timestamp, &format!("Timestamp of block {}", number))?,
+    })
+}
+
+/// Fetch the latest block to pin all reads of a snapshot to
+pub async fn get_latest_snapshot_block(provider: &Provider<Http>) -> Result<SnapshotBlock> {
+    let number = provider
+        .get_block_number()
+        .await
+        .map_err(NumbaError::from)?;
+    get_snapshot_block(provider, number).await
+}
+
+/// Find the last block mined at or before the given timestamp with a binary search over
+/// block timestamps. `latest` bounds the search from above.
+pub async fn find_block_at(
+    provider: &Provider<Http>,
+    timestamp: u64,
+    latest: &SnapshotBlock,
+) -> Result<SnapshotBlock> {
+    if timestamp >= latest.timestamp {
+        return Ok(*latest);
+    }
+
+    let mut low = get_snapshot_block(provider, U64::one()).await?;
+    if timestamp < low.timestamp {
+        return Err(anyhow!("Timestamp {} is before the first block", timestamp));
+    }
+    let mut high = *latest;
+
+    // Invariant: low.timestamp <= timestamp < high.timestamp
+    while high.number - low.number > 1 {
+        let middle = low.number + (high.number - low.number) / 2;
+        let block = get_snapshot_block(provider, U64::from(middle)).await?;
+        if block.timestamp <= timestamp {
+            low = block;
+        } else {
+            high = block;
+        }
+    }
+
+    Ok(low)
+}
@@ -1,7 +1,9 @@
 use crate::constants;
-use crate::types::{UserVaultHoldings, VaultPerformance};
+use crate::types::{
+    CashFlow, ReturnMethod, UserVaultHoldings, VaultPerformance, Window, WindowPerformance,
+};
 use crate::utils::unix_time;
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, FromPrimitive, One, ToPrimitive, Zero};
 
 /// Find the closest entry to the given timestamp
 pub fn find_closest_to(entries: &[UserVaultHoldings], timestamp: u64) -> Option<UserVaultHoldings> {
@@ -9,11 +11,7 @@ pub fn find_closest_to(entries: &[UserVaultHoldings], timestamp: u64) -> Option<
     let mut previous_entry = None;
     for entry in entries {
         // Distance direction doesn't matter, let's just have it positive
-        let distance = if timestamp > entry.timestamp {
-            timestamp - entry.timestamp
-        } else {
-            entry.timestamp - timestamp
-        };
+        let distance = timestamp.abs_diff(entry.timestamp);
 
         // We assume that entries are sorted so if the previous distance was smaller we know
         // that it won't get better from here. Return the previous entry.
@@ -29,44 +27,219 @@ pub fn find_closest_to(entries: &[UserVaultHoldings], timestamp: u64) -> Option<
 }
 
 pub struct GainInfo {
+    start_value: BigDecimal,
     gain: BigDecimal,
-    apy: BigDecimal,
+    apr: Option<BigDecimal>,
+    apy: Option<BigDecimal>,
 }
 
 impl GainInfo {
     pub fn zero() -> GainInfo {
         GainInfo {
+            start_value: BigDecimal::from(0),
             gain: BigDecimal::from(0),
-            apy: BigDecimal::from(0),
+            apr: Some(BigDecimal::from(0)),
+            apy: Some(BigDecimal::from(0)),
         }
     }
 }
 
+/// Scale a return over the given duration linearly to a year
+pub fn annual_percentage_rate(period_return: &BigDecimal, duration_sec: u64) -> BigDecimal {
+    period_return / BigDecimal::from(duration_sec) * BigDecimal::from(constants::YEAR_IN_SEC)
+}
+
+/// Compound a return over the given duration to a year: (1 + r)^(year / duration) - 1. `None` if
+/// the result doesn't fit into an f64, which happens for large returns over short durations.
+pub fn annual_percentage_yield(
+    period_return: &BigDecimal,
+    duration_sec: u64,
+) -> Option<BigDecimal> {
+    let periods_per_year = constants::YEAR_IN_SEC as f64 / duration_sec as f64;
+    period_return
+        .to_f64()
+        .map(|rate| (1.0 + rate).powf(periods_per_year) - 1.0)
+        .and_then(BigDecimal::from_f64)
+}
+
+/// Turn an annualized rate back into the return over the given duration
+fn period_return_of_annual_rate(annual_rate: &BigDecimal, duration_sec: u64) -> Option<BigDecimal> {
+    let years = duration_sec as f64 / constants::YEAR_IN_SEC as f64;
+    annual_rate
+        .to_f64()
+        .map(|rate| (1.0 + rate).powf(years) - 1.0)
+        .and_then(BigDecimal::from_f64)
+}
+
+/// The USD value of a single vault share, if the share balance is known and not zero
+fn usd_per_share(holdings: &UserVaultHoldings) -> Option<BigDecimal> {
+    match &holdings.shares {
+        Some(shares) if !shares.is_zero() => Some(&holdings.usd_all / shares),
+        _ => None,
+    }
+}
+
+/// Calculate the net deposit (positive) or withdrawal (negative) in USD between two subsequent
+/// snapshots. A change in the share count is valued at the share price of the later snapshot,
+/// or at the previous one if all shares were withdrawn. Snapshots without a recorded share
+/// balance can't tell flows apart from gains and yield no flow.
+pub fn flow_between(previous: &UserVaultHoldings, current: &UserVaultHoldings) -> BigDecimal {
+    let (previous_shares, current_shares) = match (&previous.shares, &current.shares) {
+        (Some(previous_shares), Some(current_shares)) => (previous_shares, current_shares),
+        _ => return BigDecimal::zero(),
+    };
+
+    match usd_per_share(current).or_else(|| usd_per_share(previous)) {
+        Some(price) => (current_shares - previous_shares) * price,
+        None => BigDecimal::zero(),
+    }
+}
+
+/// Calculate the gain between two subsequent snapshots that isn't caused by deposits or
+/// withdrawals
+pub fn gain_between(previous: &UserVaultHoldings, current: &UserVaultHoldings) -> BigDecimal {
+    &current.usd_all - &previous.usd_all - flow_between(previous, current)
+}
+
+/// Derive the deposits and withdrawals from the changes in the share count of the entries
+pub fn detect_flows(entries: &[UserVaultHoldings]) -> Vec<CashFlow> {
+    entries
+        .windows(2)
+        .map(|pair| CashFlow {
+            timestamp: pair[1].timestamp,
+            amount: flow_between(&pair[0], &pair[1]),
+        })
+        .filter(|flow| !flow.amount.is_zero())
+        .collect()
+}
+
+/// Calculate the time-weighted return by chaining the returns of the sub periods between the
+/// entries. Flows are attributed to the end of the sub period in which they happened.
+pub fn time_weighted_return(entries: &[UserVaultHoldings], flows: &[CashFlow]) -> BigDecimal {
+    let growth = entries.windows(2).fold(BigDecimal::one(), |growth, pair| {
+        let (previous, current) = (&pair[0], &pair[1]);
+        if previous.usd_all.is_zero() {
+            return growth;
+        }
+        let flow: BigDecimal = flows
+            .iter()
+            .filter(|flow| {
+                flow.timestamp > previous.timestamp && flow.timestamp <= current.timestamp
+            })
+            .map(|flow| &flow.amount)
+            .sum();
+        let period_growth = (&current.usd_all - flow) / &previous.usd_all;
+        (growth * period_growth).with_prec(40)
+    });
+    growth - BigDecimal::one()
+}
+
+/// Calculate the money-weighted return as the annualized internal rate of return (XIRR) of
+/// investing the first entry's value, the given flows and withdrawing the last entry's value.
+/// Returns `None` if there is no rate that makes the flows add up.
+pub fn money_weighted_return(
+    entries: &[UserVaultHoldings],
+    flows: &[CashFlow],
+) -> Option<BigDecimal> {
+    let first = entries.first()?;
+    let last = entries.last()?;
+    if last.timestamp <= first.timestamp {
+        return None;
+    }
+
+    // From the point of view of the holder, deposits are negative and withdrawals positive
+    let mut cash_flows = vec![(first.timestamp, -first.usd_all.to_f64()?)];
+    for flow in flows {
+        if flow.timestamp > first.timestamp && flow.timestamp <= last.timestamp {
+            cash_flows.push((flow.timestamp, -flow.amount.to_f64()?));
+        }
+    }
+    cash_flows.push((last.timestamp, last.usd_all.to_f64()?));
+
+    let year = constants::YEAR_IN_SEC as f64;
+    let net_present_value = |rate: f64| -> f64 {
+        cash_flows
+            .iter()
+            .map(|(timestamp, amount)| {
+                let years = (timestamp - first.timestamp) as f64 / year;
+                amount / (1.0 + rate).powf(years)
+            })
+            .sum()
+    };
+
+    // The net present value falls with a rising rate, bisect for its root
+    let (mut low, mut high) = (-0.999_999, 1e6);
+    if net_present_value(low).signum() == net_present_value(high).signum() {
+        return None;
+    }
+    for _ in 0..200 {
+        let middle = (low + high) / 2.0;
+        if net_present_value(middle).signum() == net_present_value(low).signum() {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    BigDecimal::from_f64((low + high) / 2.0)
+}
+
 /// Calculate the gains in the given past duration
 pub fn get_gain_in_past_duration(
     entries: &[UserVaultHoldings],
     now: u64,
     duration_sec: u64,
+    method: ReturnMethod,
 ) -> GainInfo {
-    let start_time = now - duration_sec;
+    // Windows that reach back before the epoch start at the first entry
+    let start_time = now.saturating_sub(duration_sec);
     let start_point = find_closest_to(entries, start_time);
     if let (Some(start_holdings), Some(now_holdings)) = (start_point, entries.last()) {
-        let gain = &now_holdings.usd_all - &start_holdings.usd_all;
         let actual_duration = now_holdings.timestamp - start_holdings.timestamp;
         if actual_duration == 0 {
             return GainInfo::zero();
         }
+
+        let window: Vec<UserVaultHoldings> = entries
+            .iter()
+            .filter(|entry| entry.timestamp >= start_holdings.timestamp)
+            .cloned()
+            .collect();
+        let flows = detect_flows(&window);
+
+        let gain: BigDecimal = window
+            .windows(2)
+            .map(|pair| gain_between(&pair[0], &pair[1]))
+            .sum();
         let scaled_gain = gain / BigDecimal::from(actual_duration) * BigDecimal::from(duration_sec);
 
-        let gain_in_percent = ((&start_holdings.usd_all + &scaled_gain) / &start_holdings.usd_all
-            - BigDecimal::from(1))
-            * BigDecimal::from(100);
-        let apy = &gain_in_percent / BigDecimal::from(duration_sec)
-            * BigDecimal::from(constants::YEAR_IN_SEC);
+        let period_return = match method {
+            ReturnMethod::TimeWeighted => Some(time_weighted_return(&window, &flows)),
+            ReturnMethod::MoneyWeighted => money_weighted_return(&window, &flows)
+                .and_then(|rate| period_return_of_annual_rate(&rate, actual_duration)),
+        };
+        // Without a rate of return, e.g. if the money-weighted return has no root, the APR and
+        // APY are unknown
+        let period_return = match period_return {
+            Some(period_return) => period_return,
+            None => {
+                return GainInfo {
+                    start_value: start_holdings.usd_all.clone(),
+                    gain: scaled_gain,
+                    apr: None,
+                    apy: None,
+                }
+            }
+        };
 
         return GainInfo {
+            start_value: start_holdings.usd_all.clone(),
             gain: scaled_gain,
-            apy,
+            apr: Some(
+                annual_percentage_rate(&period_return, actual_duration) * BigDecimal::from(100),
+            ),
+            apy: annual_percentage_yield(&period_return, actual_duration)
+                .map(|apy| apy * BigDecimal::from(100)),
         };
     }
     GainInfo::zero()
@@ -75,52 +248,97 @@ pub fn get_gain_in_past_duration(
 pub fn get_performance(
     since_last_check: BigDecimal,
     entries: &[UserVaultHoldings],
+    method: ReturnMethod,
+    windows: &[Window],
 ) -> VaultPerformance {
-    let gain_info_past_hour =
-        get_gain_in_past_duration(entries, unix_time(), constants::HOUR_IN_SEC);
-    let gain_info_past_day = get_gain_in_past_duration(entries, unix_time(), constants::DAY_IN_SEC);
-    let gain_info_past_week =
-        get_gain_in_past_duration(entries, unix_time(), constants::WEEK_IN_SEC);
-    let gain_info_past_month =
-        get_gain_in_past_duration(entries, unix_time(), constants::MONTH_IN_SEC);
-
+    let now = unix_time();
     VaultPerformance {
         gain_last_check: since_last_check,
-        gain_past_hour: gain_info_past_hour.gain,
-        apy_past_hour: gain_info_past_hour.apy,
-        gain_past_day: gain_info_past_day.gain,
-        apy_past_day: gain_info_past_day.apy,
-        gain_past_week: gain_info_past_week.gain,
-        apy_past_week: gain_info_past_week.apy,
-        gain_past_month: gain_info_past_month.gain,
-        apy_past_month: gain_info_past_month.apy,
+        windows: windows
+            .iter()
+            .map(|window| {
+                let info = get_gain_in_past_duration(entries, now, window.duration_sec, method);
+                WindowPerformance {
+                    window: window.label.clone(),
+                    start_value: info.start_value,
+                    gain: info.gain,
+                    apr: info.apr,
+                    apy: info.apy,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Average the values, weighted by the given weights. Returns zero if all weights are zero and
+/// `None` if any of the values is unknown.
+fn weighted_average<'a>(
+    pairs: impl Iterator<Item = (&'a BigDecimal, Option<&'a BigDecimal>)>,
+) -> Option<BigDecimal> {
+    let mut weighted_sum = BigDecimal::zero();
+    let mut total_weight = BigDecimal::zero();
+    for (weight, value) in pairs {
+        weighted_sum += weight * value?;
+        total_weight += weight;
+    }
+    if total_weight.is_zero() {
+        Some(BigDecimal::zero())
+    } else {
+        Some(weighted_sum / total_weight)
     }
 }
 
+/// Sum up the gains of all vaults. The APR and APY of the portfolio are the average of the
+/// vaults' rates, weighted by the USD value each vault had at the start of the window. The APY
+/// are unknown if they are unknown for any of the vaults.
 pub fn get_cumulated_performance(
     holdings: &[UserVaultHoldings],
     entries: &[VaultPerformance],
 ) -> (BigDecimal, VaultPerformance) {
     let total: BigDecimal = holdings.iter().map(|value| &value.usd_all).sum();
+    let windows = entries
+        .first()
+        .map(|first| {
+            first
+                .windows
+                .iter()
+                .enumerate()
+                .map(|(index, window)| {
+                    let of_window = || entries.iter().filter_map(|value| value.windows.get(index));
+                    WindowPerformance {
+                        window: window.window.clone(),
+                        start_value: of_window().map(|value| &value.start_value).sum(),
+                        gain: of_window().map(|value| &value.gain).sum(),
+                        apr: weighted_average(
+                            of_window().map(|value| (&value.start_value, value.apr.as_ref())),
+                        ),
+                        apy: weighted_average(
+                            of_window().map(|value| (&value.start_value, value.apy.as_ref())),
+                        ),
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
     let total_performance = VaultPerformance {
         gain_last_check: entries.iter().map(|value| &value.gain_last_check).sum(),
-        gain_past_hour: entries.iter().map(|value| &value.gain_past_hour).sum(),
-        gain_past_day: entries.iter().map(|value| &value.gain_past_day).sum(),
-        gain_past_week: entries.iter().map(|value| &value.gain_past_week).sum(),
-        gain_past_month: entries.iter().map(|value| &value.gain_past_month).sum(),
-        apy_past_hour: BigDecimal::from(0),
-        apy_past_day: BigDecimal::from(0),
-        apy_past_week: BigDecimal::from(0),
-        apy_past_month: BigDecimal::from(0),
+        windows,
     };
     (total, total_performance)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::calculations::{find_closest_to, get_gain_in_past_duration};
-    use crate::types::UserVaultHoldings;
-    use bigdecimal::BigDecimal;
+    use crate::calculations::{
+        annual_percentage_rate, annual_percentage_yield, detect_flows, find_closest_to,
+        gain_between, get_cumulated_performance, get_gain_in_past_duration, money_weighted_return,
+        time_weighted_return,
+    };
+    use crate::constants::{DAY_IN_SEC, YEAR_IN_SEC};
+    use crate::types::{
+        CashFlow, ReturnMethod, UserVaultHoldings, VaultPerformance, Window, WindowPerformance,
+    };
+    use bigdecimal::{BigDecimal, ToPrimitive};
 
     impl UserVaultHoldings {
         pub fn with_timestamp(timestamp: u64) -> UserVaultHoldings {
@@ -135,6 +353,30 @@ mod tests {
             custom.usd_all = BigDecimal::from(value);
             custom
         }
+
+        pub fn with_shares(timestamp: u64, shares: u64, value: u64) -> UserVaultHoldings {
+            let mut custom = UserVaultHoldings::with_timestamp_and_value(timestamp, value);
+            custom.shares = Some(BigDecimal::from(shares));
+            custom
+        }
+    }
+
+    impl VaultPerformance {
+        pub fn zero() -> VaultPerformance {
+            VaultPerformance {
+                gain_last_check: BigDecimal::from(0),
+                windows: Window::defaults()
+                    .iter()
+                    .map(|window| WindowPerformance {
+                        window: window.label.clone(),
+                        start_value: BigDecimal::from(0),
+                        gain: BigDecimal::from(0),
+                        apr: Some(BigDecimal::from(0)),
+                        apy: Some(BigDecimal::from(0)),
+                    })
+                    .collect(),
+            }
+        }
     }
 
     #[test]
@@ -198,10 +440,167 @@ mod tests {
 
         let now = 40;
 
-        let first = get_gain_in_past_duration(&entries, now, 20);
+        let first = get_gain_in_past_duration(&entries, now, 20, ReturnMethod::TimeWeighted);
         assert_eq!(first.gain, BigDecimal::from(80));
 
-        let second = get_gain_in_past_duration(&entries, now, 15);
+        let second = get_gain_in_past_duration(&entries, now, 15, ReturnMethod::TimeWeighted);
         assert_eq!(second.gain, BigDecimal::from(60));
+
+        // A window reaching back before the epoch starts at the first entry
+        let all = get_gain_in_past_duration(&entries, now, 1000, ReturnMethod::TimeWeighted);
+        assert_eq!(all.start_value, BigDecimal::from(10));
+    }
+
+    #[test]
+    fn test_gain_between_excludes_flows() {
+        // Share price goes from 1.0 to 1.1 while 100 shares are deposited
+        let previous = UserVaultHoldings::with_shares(1, 100, 100);
+        let current = UserVaultHoldings::with_shares(2, 200, 220);
+        assert_eq!(gain_between(&previous, &current), BigDecimal::from(10));
+
+        // First deposit
+        let empty = UserVaultHoldings::with_shares(1, 0, 0);
+        assert_eq!(gain_between(&empty, &previous), BigDecimal::from(0));
+
+        // Full withdrawal
+        assert_eq!(gain_between(&previous, &empty), BigDecimal::from(0));
+
+        // Without share balances the difference in value is all we have
+        let legacy = UserVaultHoldings::with_timestamp_and_value(1, 100);
+        assert_eq!(gain_between(&legacy, &current), BigDecimal::from(120));
+    }
+
+    #[test]
+    fn test_get_gain_for_duration_with_deposit() {
+        let entries: Vec<UserVaultHoldings> = vec![
+            UserVaultHoldings::with_shares(0, 100, 100),
+            UserVaultHoldings::with_shares(10, 100, 110),
+            // Deposit of 10000 at a share price of 1.1
+            UserVaultHoldings::with_shares(20, 10100, 11110),
+        ];
+
+        let info = get_gain_in_past_duration(&entries, 20, 20, ReturnMethod::TimeWeighted);
+        assert_eq!(info.gain, BigDecimal::from(10));
+        assert_eq!(
+            info.apr,
+            Some(BigDecimal::from(10) / BigDecimal::from(20) * BigDecimal::from(YEAR_IN_SEC))
+        );
+    }
+
+    #[test]
+    fn test_time_weighted_return_ignores_flows() {
+        let entries: Vec<UserVaultHoldings> = vec![
+            UserVaultHoldings::with_shares(0, 100, 100),
+            // +10 %
+            UserVaultHoldings::with_shares(10, 100, 110),
+            // Deposit of 110 at a share price of 1.1
+            UserVaultHoldings::with_shares(20, 200, 220),
+            // +10 %
+            UserVaultHoldings::with_shares(30, 200, 242),
+        ];
+
+        let flows = detect_flows(&entries);
+        assert_eq!(
+            flows,
+            vec![CashFlow {
+                timestamp: 20,
+                amount: BigDecimal::from(110)
+            }]
+        );
+        assert_eq!(
+            time_weighted_return(&entries, &flows),
+            "0.21".parse::<BigDecimal>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_money_weighted_return() {
+        // Invest 100 and get back 110 after one year
+        let entries: Vec<UserVaultHoldings> = vec![
+            UserVaultHoldings::with_shares(0, 100, 100),
+            UserVaultHoldings::with_shares(YEAR_IN_SEC, 100, 110),
+        ];
+        let rate = money_weighted_return(&entries, &[]).unwrap();
+        assert!((rate.to_f64().unwrap() - 0.1).abs() < 1e-9);
+
+        // A deposit of 100 half way that earns nothing dilutes the return
+        let entries: Vec<UserVaultHoldings> = vec![
+            UserVaultHoldings::with_shares(0, 100, 100),
+            UserVaultHoldings::with_shares(YEAR_IN_SEC / 2, 200, 200),
+            UserVaultHoldings::with_shares(YEAR_IN_SEC, 200, 210),
+        ];
+        let flows = detect_flows(&entries);
+        let rate = money_weighted_return(&entries, &flows).unwrap();
+        assert!(rate.to_f64().unwrap() > 0.05 && rate.to_f64().unwrap() < 0.1);
+    }
+
+    #[test]
+    fn test_money_weighted_return_without_root_is_unknown() {
+        // Starting at a zero balance, the net present value is positive for every rate
+        let entries: Vec<UserVaultHoldings> = vec![
+            UserVaultHoldings::with_timestamp_and_value(0, 0),
+            UserVaultHoldings::with_timestamp_and_value(DAY_IN_SEC, 100),
+        ];
+        assert_eq!(money_weighted_return(&entries, &[]), None);
+
+        let info = get_gain_in_past_duration(
+            &entries,
+            DAY_IN_SEC,
+            DAY_IN_SEC,
+            ReturnMethod::MoneyWeighted,
+        );
+        assert_eq!(info.apr, None);
+        assert_eq!(info.apy, None);
+    }
+
+    #[test]
+    fn test_apr_and_compounded_apy() {
+        let one_percent = "0.01".parse::<BigDecimal>().unwrap();
+        let month = 30 * DAY_IN_SEC;
+
+        let apr = annual_percentage_rate(&one_percent, month);
+        assert!((apr.to_f64().unwrap() - 0.01 * 365.0 / 30.0).abs() < 1e-12);
+
+        let apy = annual_percentage_yield(&one_percent, month).unwrap();
+        assert!((apy.to_f64().unwrap() - (1.01f64.powf(365.0 / 30.0) - 1.0)).abs() < 1e-12);
+        assert!(apy > apr);
+
+        // Over exactly one year both are the same
+        let apy = annual_percentage_yield(&one_percent, YEAR_IN_SEC).unwrap();
+        assert!((apy.to_f64().unwrap() - 0.01).abs() < 1e-12);
+
+        // Doubling within an hour compounds to more than an f64 can hold
+        assert_eq!(annual_percentage_yield(&BigDecimal::from(1), 3600), None);
+    }
+
+    #[test]
+    fn test_cumulated_apy_is_weighted_by_start_value() {
+        let performance = |start_value: u64, apy: u64| {
+            let mut performance = VaultPerformance::zero();
+            let past_day = &mut performance.windows[1];
+            past_day.start_value = BigDecimal::from(start_value);
+            past_day.apr = Some(BigDecimal::from(apy));
+            past_day.apy = Some(BigDecimal::from(apy));
+            performance
+        };
+
+        let (_, total) = get_cumulated_performance(
+            &[UserVaultHoldings::zero(), UserVaultHoldings::zero()],
+            &[performance(300, 10), performance(100, 30)],
+        );
+        assert_eq!(total.windows[1].window, "day");
+        assert_eq!(total.windows[1].start_value, BigDecimal::from(400));
+        assert_eq!(total.windows[1].apr, Some(BigDecimal::from(15)));
+        assert_eq!(total.windows[1].apy, Some(BigDecimal::from(15)));
+        assert_eq!(total.windows[2].apy, Some(BigDecimal::from(0)));
+
+        let mut unknown = performance(100, 30);
+        unknown.windows[1].apy = None;
+        let (_, total) = get_cumulated_performance(
+            &[UserVaultHoldings::zero(), UserVaultHoldings::zero()],
+            &[performance(300, 10), unknown],
+        );
+        assert_eq!(total.windows[1].apr, Some(BigDecimal::from(15)));
+        assert_eq!(total.windows[1].apy, None);
     }
 }
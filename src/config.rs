@@ -0,0 +1,212 @@
+use anyhow::{bail, Result};
+use ethers::types::Address;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::NumbaError;
+use crate::provider::is_ens_name;
+use crate::store::{get_app_dir, StoreBackend};
+use crate::types::{OutputFormat, ReturnMethod, Window};
+use crate::utils::parse_duration;
+use crate::vaults::default_vaults;
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub rpc_url: Option<String>,
+    pub store: Option<StoreBackend>,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub holders: Vec<HolderConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    pub format: Option<OutputFormat>,
+    pub return_method: Option<ReturnMethod>,
+    /// The windows of the report, e.g. `["1h", "1d", "1w", "30d"]`
+    pub windows: Option<Vec<String>>,
+}
+
+/// A named holder address
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HolderConfig {
+    pub label: String,
+    pub address: String,
+    /// The names of the vaults to track, all vaults if not set
+    pub vaults: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Check the values that the TOML schema alone can't
+    pub fn validate(&self) -> Result<()> {
+        if matches!(&self.rpc_url, Some(url) if url.trim().is_empty()) {
+            bail!("rpc_url must not be empty");
+        }
+
+        if let Some(windows) = &self.output.windows {
+            if windows.is_empty() {
+                bail!("output.windows must list at least one window");
+            }
+            for window in windows {
+                if let Err(err) = parse_duration(window) {
+                    bail!("output.windows: {}", err);
+                }
+            }
+        }
+
+        let known_vaults: Vec<String> = default_vaults()
+            .iter()
+            .map(|vault| vault.name().to_owned())
+            .collect();
+        let mut labels = HashSet::new();
+        for holder in &self.holders {
+            if holder.label.is_empty() {
+                bail!("Holder {} has an empty label", holder.address);
+            }
+            if !labels.insert(&holder.label) {
+                bail!("Holder label '{}' is used more than once", holder.label);
+            }
+            if holder.address.parse::<Address>().is_err() && !is_ens_name(&holder.address) {
+                bail!(
+                    "Holder '{}' has an invalid address '{}'",
+                    holder.label,
+                    holder.address
+                );
+            }
+            for vault in holder.vaults.iter().flatten() {
+                if !known_vaults.contains(vault) {
+                    bail!(
+                        "Holder '{}' has an unknown vault '{}', expected one of {}",
+                        holder.label,
+                        vault,
+                        known_vaults.join(", ")
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The windows of the report, the past hour, day, week and month if not configured
+    pub fn windows(&self) -> Vec<Window> {
+        match &self.output.windows {
+            Some(windows) => windows
+                .iter()
+                .filter_map(|window| {
+                    parse_duration(window)
+                        .ok()
+                        .map(|duration_sec| Window::new(window, duration_sec))
+                })
+                .collect(),
+            None => Window::defaults(),
+        }
+    }
+
+    pub fn holder(&self, label: &str) -> Option<&HolderConfig> {
+        self.holders.iter().find(|holder| holder.label == label)
+    }
+}
+
+pub fn get_config_path() -> Result<PathBuf> {
+    get_app_dir()
+        .map(|path| path.join(CONFIG_FILE_NAME))
+        .map_err(|err| anyhow::anyhow!(err))
+}
+
+/// Load the config from the application directory. A missing config file is not an error
+/// and yields the default config.
+pub fn load_default_config() -> Result<Config> {
+    load_config(&get_config_path()?)
+}
+
+pub fn load_config(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|err| NumbaError::Config(format!("Can't read {}: {}", path.display(), err)))?;
+    let config: Config = toml::from_str(&content)
+        .map_err(|err| NumbaError::Config(format!("{}: {}", path.display(), err)))?;
+    config
+        .validate()
+        .map_err(|err| NumbaError::Config(format!("{}: {:#}", path.display(), err)))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::load_config;
+    use crate::types::{OutputFormat, Window};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_missing_config_is_default() {
+        let dir = tempdir().unwrap();
+        let config = load_config(&dir.path().join("config.toml")).unwrap();
+        assert_eq!(config.rpc_url, None);
+    }
+
+    #[test]
+    fn test_load_rpc_url() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "rpc_url = \"http://localhost:8545\"\n").unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.rpc_url, Some("http://localhost:8545".to_owned()));
+    }
+
+    #[test]
+    fn test_load_holders_and_output() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            r#"
+[output]
+format = "json"
+windows = ["6h", "1w"]
+
+[[holders]]
+label = "treasury"
+address = "0x2a4d5b5f1a6c0f0d8ee9e5b5a0c64f1c9b6a6f2e"
+vaults = ["crvSAAVE"]
+"#,
+        )
+        .unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.output.format, Some(OutputFormat::Json));
+        assert_eq!(
+            config.windows(),
+            vec![Window::new("6h", 6 * 3600), Window::new("1w", 7 * 86400)]
+        );
+        let holder = config.holder("treasury").unwrap();
+        assert_eq!(holder.vaults, Some(vec!["crvSAAVE".to_owned()]));
+    }
+
+    #[test]
+    fn test_reject_unknown_vault() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            r#"
+[[holders]]
+label = "treasury"
+address = "0x2a4d5b5f1a6c0f0d8ee9e5b5a0c64f1c9b6a6f2e"
+vaults = ["crv3POOL"]
+"#,
+        )
+        .unwrap();
+        let err = load_config(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown vault 'crv3POOL'"));
+    }
+}
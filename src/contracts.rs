@@ -1,15 +1,37 @@
-use anyhow::Result;
-use ethers::{abi::Abi, abi::Uint, prelude::*};
+use ethers::{
+    abi::Abi,
+    abi::Uint,
+    contract::{builders::ContractCall, Multicall},
+    prelude::*,
+};
 
-use std::str::FromStr;
+use crate::error::NumbaError;
+use crate::utils::u256_to_u64;
 
-use crate::constants::*;
+fn parse_address(address: &str) -> Result<Address, NumbaError> {
+    address
+        .parse::<Address>()
+        .map_err(|_| NumbaError::InvalidAddress(format!("'{}' is not a contract address", address)))
+}
+
+fn new_contract(
+    provider: &Provider<Http>,
+    abi: &str,
+    address: &str,
+) -> Result<Contract<Provider<Http>>, NumbaError> {
+    new_contract_at(provider, abi, parse_address(address)?)
+}
 
-fn new_contract(provider: &Provider<Http>, abi: &str, address: &str) -> Contract<Provider<Http>> {
-    let abi: Abi = serde_json::from_str(abi).expect("Can't load ABI");
-    let address = address.parse::<Address>().expect("Invalid address");
+fn new_contract_at(
+    provider: &Provider<Http>,
+    abi: &str,
+    address: Address,
+) -> Result<Contract<Provider<Http>>, NumbaError> {
+    let abi: Abi = serde_json::from_str(abi).map_err(|err| {
+        NumbaError::UnexpectedResponse(format!("Can't load ABI of {:?}: {}", address, err))
+    })?;
 
-    Contract::new(address, abi, provider.clone())
+    Ok(Contract::new(address, abi, provider.clone()))
 }
 
 // struct CToken {
@@ -36,28 +58,106 @@ pub struct CurveRegistry {
 }
 
 impl CurveRegistry {
-    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Self {
-        CurveRegistry {
-            contract: new_contract(provider, abi, address),
-        }
+    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Result<Self, NumbaError> {
+        Ok(CurveRegistry {
+            contract: new_contract(provider, abi, address)?,
+        })
+    }
+
+    pub async fn get_lp_token(
+        &self,
+        pool: Address,
+        block: BlockNumber,
+    ) -> Result<Address, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Address>("get_lp_token", pool)?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    pub async fn get_pool_from_lp_token(
+        &self,
+        lp_token: Address,
+        block: BlockNumber,
+    ) -> Result<Address, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Address>("get_pool_from_lp_token", lp_token)?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    /// Returns the number of coins and the number of underlying coins of the pool
+    pub async fn get_n_coins(
+        &self,
+        pool: Address,
+        block: BlockNumber,
+    ) -> Result<Vec<Uint>, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Vec<Uint>>("get_n_coins", pool)?
+            .block(block)
+            .call()
+            .await?)
     }
 
-    pub async fn get_comp_dai_usdc(&self) -> Result<Vec<Uint>, ContractError<Provider<Http>>> {
-        // FIXME: Don't hardcode pool
-        let address = Address::from_str(CURVE_COMP_POOL_ADDRESS).unwrap();
-        self.contract
-            .method::<_, Vec<Uint>>("get_underlying_balances", address)?
+    pub async fn get_coins(
+        &self,
+        pool: Address,
+        block: BlockNumber,
+    ) -> Result<Vec<Address>, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Vec<Address>>("get_coins", pool)?
+            .block(block)
             .call()
-            .await
+            .await?)
     }
 
-    pub async fn get_saave_dai_susd(&self) -> Result<Vec<Uint>, ContractError<Provider<Http>>> {
-        // FIXME: Don't hardcode pool
-        let address = Address::from_str(CURVE_SAAVE_POOL_ADDRESS).unwrap();
-        self.contract
-            .method::<_, Vec<Uint>>("get_balances", address)?
+    pub async fn get_underlying_coins(
+        &self,
+        pool: Address,
+        block: BlockNumber,
+    ) -> Result<Vec<Address>, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Vec<Address>>("get_underlying_coins", pool)?
+            .block(block)
             .call()
-            .await
+            .await?)
+    }
+
+    #[allow(dead_code)]
+    pub async fn get_decimals(
+        &self,
+        pool: Address,
+        block: BlockNumber,
+    ) -> Result<Vec<Uint>, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Vec<Uint>>("get_decimals", pool)?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    pub fn get_balances_call(
+        &self,
+        pool: Address,
+    ) -> Result<ContractCall<Provider<Http>, Vec<Uint>>, NumbaError> {
+        Ok(self.contract.method::<_, Vec<Uint>>("get_balances", pool)?)
+    }
+
+    pub fn get_underlying_balances_call(
+        &self,
+        pool: Address,
+    ) -> Result<ContractCall<Provider<Http>, Vec<Uint>>, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Vec<Uint>>("get_underlying_balances", pool)?)
     }
 }
 
@@ -66,17 +166,44 @@ pub struct CurvePoolLpToken {
 }
 
 impl CurvePoolLpToken {
-    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Self {
-        CurvePoolLpToken {
-            contract: new_contract(provider, abi, address),
-        }
+    pub fn at(provider: &Provider<Http>, abi: &str, address: Address) -> Result<Self, NumbaError> {
+        Ok(CurvePoolLpToken {
+            contract: new_contract_at(provider, abi, address)?,
+        })
+    }
+
+    pub fn total_supply_call(&self) -> Result<ContractCall<Provider<Http>, Uint>, NumbaError> {
+        Ok(self.contract.method::<_, Uint>("totalSupply", ())?)
+    }
+}
+
+pub struct Erc20Token {
+    contract: Contract<Provider<Http>>,
+}
+
+impl Erc20Token {
+    pub fn at(provider: &Provider<Http>, abi: &str, address: Address) -> Result<Self, NumbaError> {
+        Ok(Erc20Token {
+            contract: new_contract_at(provider, abi, address)?,
+        })
+    }
+
+    pub async fn symbol(&self, block: BlockNumber) -> Result<String, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, String>("symbol", ())?
+            .block(block)
+            .call()
+            .await?)
     }
 
-    pub async fn total_supply(&self) -> Result<Uint, ContractError<Provider<Http>>> {
-        self.contract
-            .method::<_, Uint>("totalSupply", ())?
+    pub async fn decimals(&self, block: BlockNumber) -> Result<u8, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, u8>("decimals", ())?
+            .block(block)
             .call()
-            .await
+            .await?)
     }
 }
 
@@ -85,26 +212,218 @@ pub struct YearnVaultV2 {
 }
 
 impl YearnVaultV2 {
-    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Self {
-        YearnVaultV2 {
-            contract: new_contract(provider, abi, address),
-        }
+    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Result<Self, NumbaError> {
+        Ok(YearnVaultV2 {
+            contract: new_contract(provider, abi, address)?,
+        })
+    }
+
+    pub fn at(provider: &Provider<Http>, abi: &str, address: Address) -> Result<Self, NumbaError> {
+        Ok(YearnVaultV2 {
+            contract: new_contract_at(provider, abi, address)?,
+        })
     }
 
-    pub async fn get_price_per_share(&self) -> Result<Uint, ContractError<Provider<Http>>> {
-        self.contract
-            .method::<_, Uint>("pricePerShare", ())?
+    pub fn address(&self) -> Address {
+        self.contract.address()
+    }
+
+    pub async fn token(&self, block: BlockNumber) -> Result<Address, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Address>("token", ())?
+            .block(block)
             .call()
-            .await
+            .await?)
+    }
+
+    pub fn get_price_per_share_call(
+        &self,
+    ) -> Result<ContractCall<Provider<Http>, Uint>, NumbaError> {
+        Ok(self.contract.method::<_, Uint>("pricePerShare", ())?)
+    }
+
+    pub async fn get_price_per_share(&self, block: BlockNumber) -> Result<Uint, NumbaError> {
+        Ok(self.get_price_per_share_call()?.block(block).call().await?)
+    }
+
+    pub fn balance_of_call(
+        &self,
+        address: Address,
+    ) -> Result<ContractCall<Provider<Http>, Uint>, NumbaError> {
+        Ok(self.contract.method::<_, Uint>("balanceOf", address)?)
     }
 
     pub async fn balance_of(
         &self,
         address: Address,
-    ) -> Result<Uint, ContractError<Provider<Http>>> {
-        self.contract
-            .method::<_, Uint>("balanceOf", address)?
+        block: BlockNumber,
+    ) -> Result<Uint, NumbaError> {
+        Ok(self.balance_of_call(address)?.block(block).call().await?)
+    }
+}
+
+pub struct YearnRegistry {
+    contract: Contract<Provider<Http>>,
+}
+
+impl YearnRegistry {
+    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Result<Self, NumbaError> {
+        Ok(YearnRegistry {
+            contract: new_contract(provider, abi, address)?,
+        })
+    }
+
+    pub async fn num_tokens(&self, block: BlockNumber) -> Result<Uint, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Uint>("numTokens", ())?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    pub async fn token(&self, index: Uint, block: BlockNumber) -> Result<Address, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Address>("tokens", index)?
+            .block(block)
             .call()
-            .await
+            .await?)
+    }
+
+    pub async fn num_vaults(&self, token: Address, block: BlockNumber) -> Result<Uint, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Uint>("numVaults", token)?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    pub async fn vault(
+        &self,
+        token: Address,
+        index: Uint,
+        block: BlockNumber,
+    ) -> Result<Address, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, Address>("vaults", (token, index))?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    /// Enumerate the addresses of all endorsed vaults
+    pub async fn all_vaults(&self, block: BlockNumber) -> Result<Vec<Address>, NumbaError> {
+        let mut vaults = vec![];
+        let num_tokens = u256_to_u64(self.num_tokens(block).await?, "Number of yearn tokens")?;
+        for token_index in 0..num_tokens {
+            let token = self.token(token_index.into(), block).await?;
+            let num_vaults = u256_to_u64(
+                self.num_vaults(token, block).await?,
+                "Number of yearn vaults",
+            )?;
+            for vault_index in 0..num_vaults {
+                vaults.push(self.vault(token, vault_index.into(), block).await?);
+            }
+        }
+        Ok(vaults)
+    }
+}
+
+/// The latest round of a Chainlink price feed
+#[derive(Debug, Clone)]
+pub struct RoundData {
+    pub answer: I256,
+    pub updated_at: Uint,
+}
+
+pub struct ChainlinkAggregator {
+    contract: Contract<Provider<Http>>,
+}
+
+impl ChainlinkAggregator {
+    pub fn new(provider: &Provider<Http>, abi: &str, address: &str) -> Result<Self, NumbaError> {
+        Ok(ChainlinkAggregator {
+            contract: new_contract(provider, abi, address)?,
+        })
+    }
+
+    pub async fn decimals(&self, block: BlockNumber) -> Result<u8, NumbaError> {
+        Ok(self
+            .contract
+            .method::<_, u8>("decimals", ())?
+            .block(block)
+            .call()
+            .await?)
+    }
+
+    pub async fn latest_round_data(&self, block: BlockNumber) -> Result<RoundData, NumbaError> {
+        let (_round_id, answer, _started_at, updated_at, _answered_in_round) = self
+            .contract
+            .method::<_, (Uint, I256, Uint, Uint, Uint)>("latestRoundData", ())?
+            .block(block)
+            .call()
+            .await?;
+        Ok(RoundData { answer, updated_at })
+    }
+}
+
+/// The contract reads that make up a holdings snapshot
+pub struct SnapshotCalls {
+    pub price_per_share: ContractCall<Provider<Http>, Uint>,
+    pub vault_shares: ContractCall<Provider<Http>, Uint>,
+    pub total_lp_tokens: ContractCall<Provider<Http>, Uint>,
+    pub pool_balances: ContractCall<Provider<Http>, Vec<Uint>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotReads {
+    pub price_per_share: Uint,
+    pub vault_shares: Uint,
+    pub total_lp_tokens: Uint,
+    pub pool_balances: Vec<Uint>,
+}
+
+/// Batches contract reads into a single `eth_call` through the Multicall contract, which
+/// guarantees that all of them are resolved against the same block.
+pub struct MulticallReader {
+    provider: Provider<Http>,
+    address: Address,
+}
+
+impl MulticallReader {
+    pub fn new(provider: &Provider<Http>, address: &str) -> Result<Self, NumbaError> {
+        Ok(MulticallReader {
+            provider: provider.clone(),
+            address: parse_address(address)?,
+        })
+    }
+
+    pub async fn read_snapshot(
+        &self,
+        calls: SnapshotCalls,
+        block: BlockNumber,
+    ) -> Result<SnapshotReads, NumbaError> {
+        let mut multicall = Multicall::new(self.provider.clone(), Some(self.address))
+            .await?
+            .block(block);
+        multicall
+            .add_call(calls.price_per_share)
+            .add_call(calls.vault_shares)
+            .add_call(calls.total_lp_tokens)
+            .add_call(calls.pool_balances);
+
+        let (price_per_share, vault_shares, total_lp_tokens, pool_balances) =
+            multicall.call::<(Uint, Uint, Uint, Vec<Uint>)>().await?;
+
+        Ok(SnapshotReads {
+            price_per_share,
+            vault_shares,
+            total_lp_tokens,
+            pool_balances,
+        })
     }
 }
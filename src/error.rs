@@ -0,0 +1,75 @@
+use ethers::contract::AbiError;
+use ethers::prelude::*;
+use thiserror::Error;
+
+/// The failures numbagoup reports to the user. Each kind exits with its own code.
+#[derive(Debug, Error)]
+pub enum NumbaError {
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("RPC request failed: {0}")]
+    Rpc(String),
+    #[error("Unexpected contract response: {0}")]
+    UnexpectedResponse(String),
+    #[error("Corrupt database row: {0}")]
+    CorruptRow(String),
+    #[error("Invalid config: {0}")]
+    Config(String),
+}
+
+impl NumbaError {
+    /// The exit code of the process, following the conventions of sysexits.h
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NumbaError::InvalidAddress(_) => 64,
+            NumbaError::CorruptRow(_) => 65,
+            NumbaError::Rpc(_) => 69,
+            NumbaError::UnexpectedResponse(_) => 76,
+            NumbaError::Config(_) => 78,
+        }
+    }
+}
+
+impl From<ProviderError> for NumbaError {
+    fn from(err: ProviderError) -> Self {
+        NumbaError::Rpc(err.to_string())
+    }
+}
+
+impl From<AbiError> for NumbaError {
+    fn from(err: AbiError) -> Self {
+        NumbaError::UnexpectedResponse(err.to_string())
+    }
+}
+
+impl From<ContractError<Provider<Http>>> for NumbaError {
+    fn from(err: ContractError<Provider<Http>>) -> Self {
+        match err {
+            ContractError::ProviderError(err) => NumbaError::Rpc(err.to_string()),
+            ContractError::MiddlewareError(err) => NumbaError::Rpc(err.to_string()),
+            err => NumbaError::UnexpectedResponse(err.to_string()),
+        }
+    }
+}
+
+/// The exit code for an error, 1 if no `NumbaError` caused it
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<NumbaError>())
+        .map_or(1, NumbaError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{exit_code, NumbaError};
+    use anyhow::Context;
+
+    #[test]
+    fn test_exit_code_of_wrapped_error() {
+        let err = Err::<(), _>(NumbaError::Rpc("timeout".to_owned()))
+            .context("Can't take snapshot")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 69);
+        assert_eq!(exit_code(&anyhow::anyhow!("Something else")), 1);
+    }
+}
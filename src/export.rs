@@ -0,0 +1,273 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::calculations::gain_between;
+use crate::store::migrations::legacy_coins;
+use crate::types::{DbCoinHolding, DbUserVaultHoldings, UserVaultHoldings};
+
+/// The file formats snapshots can be exported to and imported from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("Unknown format '{}', expected csv or json", value)),
+        }
+    }
+}
+
+/// A snapshot together with the gain since the snapshot before it. Decimals are written as
+/// strings to keep their precision.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    #[serde(flatten)]
+    pub entry: DbUserVaultHoldings,
+    /// Empty for the first snapshot of a group
+    pub gain: Option<String>,
+}
+
+/// Turn the entries of a group into rows, keeping those with `from <= timestamp <= to`. The gain
+/// of the first row in the range is still taken against the snapshot before it.
+pub fn export_rows(
+    entries: &[UserVaultHoldings],
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Vec<ExportRow> {
+    let mut previous: Option<&UserVaultHoldings> = None;
+    let mut rows = vec![];
+    for entry in entries {
+        let in_range = from.is_none_or(|from| entry.timestamp >= from)
+            && to.is_none_or(|to| entry.timestamp <= to);
+        if in_range {
+            rows.push(ExportRow {
+                entry: entry.into(),
+                gain: previous.map(|previous| gain_between(previous, entry).to_string()),
+            });
+        }
+        previous = Some(entry);
+    }
+    rows
+}
+
+pub fn write_rows(rows: &[ExportRow], format: ExportFormat, writer: impl Write) -> Result<()> {
+    match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(writer, rows)?,
+        ExportFormat::Csv => {
+            // The csv crate can't serialize flattened structs, so write the columns by hand
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record([
+                "timestamp",
+                "block_number",
+                "price_per_share",
+                "shares",
+                "coins",
+                "usd_all",
+                "gain",
+            ])?;
+            for row in rows {
+                let entry = &row.entry;
+                writer.write_record([
+                    entry.timestamp.to_string(),
+                    entry.block_number.to_string(),
+                    entry.price_per_share.clone(),
+                    entry.shares.clone().unwrap_or_default(),
+                    serde_json::to_string(&entry.coins)?,
+                    entry.usd_all.clone(),
+                    row.gain.clone().unwrap_or_default(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// A row as written by `write_rows`, or by versions that wrote the USD values of two coins in
+/// `usd_1` and `usd_2` instead of the coins. `C` is how the format encodes the coins.
+#[derive(Debug, Deserialize)]
+struct ImportRow<C> {
+    timestamp: u64,
+    #[serde(default)]
+    block_number: u64,
+    price_per_share: String,
+    #[serde(default)]
+    shares: Option<String>,
+    #[serde(default)]
+    coins: Option<C>,
+    #[serde(default)]
+    usd_1: Option<String>,
+    #[serde(default)]
+    usd_2: Option<String>,
+    usd_all: String,
+}
+
+impl<C> ImportRow<C> {
+    fn into_entry(
+        self,
+        vault_name: &str,
+        parse_coins: impl Fn(C) -> Result<Vec<DbCoinHolding>>,
+    ) -> Result<DbUserVaultHoldings> {
+        let coins = match (self.coins, self.usd_1, self.usd_2) {
+            (Some(coins), _, _) => parse_coins(coins)?,
+            (None, Some(usd_1), Some(usd_2)) => legacy_coins(vault_name, &usd_1, &usd_2),
+            _ => return Err(anyhow!("Missing coins")),
+        };
+        Ok(DbUserVaultHoldings {
+            timestamp: self.timestamp,
+            block_number: self.block_number,
+            price_per_share: self.price_per_share,
+            shares: self.shares,
+            coins,
+            usd_all: self.usd_all,
+        })
+    }
+}
+
+/// Parse rows as written by `write_rows` for the vault `vault_name`. The gain column is ignored
+/// and missing block numbers and share balances are treated as unknown.
+pub fn read_rows(
+    reader: impl Read,
+    format: ExportFormat,
+    vault_name: &str,
+) -> Result<Vec<DbUserVaultHoldings>> {
+    let rows = match format {
+        ExportFormat::Json => {
+            let rows: Vec<ImportRow<Vec<DbCoinHolding>>> = serde_json::from_reader(reader)?;
+            into_entries(rows, vault_name, Ok)?
+        }
+        // The coins column holds the coins as a JSON list
+        ExportFormat::Csv => {
+            let rows = deserialize_csv::<ImportRow<String>>(reader)?;
+            into_entries(rows, vault_name, |coins| Ok(serde_json::from_str(&coins)?))?
+        }
+    };
+
+    for (index, row) in rows.iter().enumerate() {
+        UserVaultHoldings::try_from(row).with_context(|| format!("Invalid row {}", index + 1))?;
+    }
+    Ok(rows)
+}
+
+fn deserialize_csv<T: DeserializeOwned>(reader: impl Read) -> Result<Vec<T>> {
+    csv::Reader::from_reader(reader)
+        .deserialize()
+        .enumerate()
+        .map(|(index, row)| row.with_context(|| format!("Invalid row {}", index + 1)))
+        .collect()
+}
+
+fn into_entries<C>(
+    rows: Vec<ImportRow<C>>,
+    vault_name: &str,
+    parse_coins: impl Fn(C) -> Result<Vec<DbCoinHolding>>,
+) -> Result<Vec<DbUserVaultHoldings>> {
+    rows.into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            row.into_entry(vault_name, &parse_coins)
+                .with_context(|| format!("Invalid row {}", index + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::{export_rows, read_rows, write_rows, ExportFormat};
+    use crate::types::{CoinHolding, UserVaultHoldings};
+    use bigdecimal::BigDecimal;
+
+    #[test]
+    fn test_export_rows_in_range_with_gain() {
+        let entries = vec![
+            UserVaultHoldings::with_timestamp_and_value(100, 10),
+            UserVaultHoldings::with_timestamp_and_value(200, 12),
+            UserVaultHoldings::with_timestamp_and_value(300, 15),
+        ];
+
+        let rows = export_rows(&entries, Some(200), None);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].entry.timestamp, 200);
+        assert_eq!(rows[0].gain, Some("2".to_owned()));
+        assert_eq!(rows[1].gain, Some("3".to_owned()));
+
+        let rows = export_rows(&entries, None, Some(100));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].gain, None);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let rows = export_rows(
+            &[
+                UserVaultHoldings::with_timestamp_and_value(100, 10),
+                UserVaultHoldings::with_timestamp_and_value(200, 12),
+            ],
+            None,
+            None,
+        );
+        let mut out = vec![];
+        write_rows(&rows, ExportFormat::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,block_number,price_per_share,shares,coins,usd_all,gain\n\
+             100,0,0,,[],10,\n\
+             200,0,0,,[],12,2\n"
+        );
+    }
+
+    #[test]
+    fn test_read_rows_round_trip() {
+        let mut entries = vec![
+            UserVaultHoldings::with_timestamp_and_value(100, 10),
+            UserVaultHoldings::with_timestamp_and_value(200, 12),
+        ];
+        entries[1].coins.push(CoinHolding {
+            symbol: "DAI".to_owned(),
+            amount: BigDecimal::from(12),
+            usd: BigDecimal::from(12),
+        });
+        let rows = export_rows(&entries, None, None);
+
+        for format in [ExportFormat::Csv, ExportFormat::Json].iter() {
+            let mut out = vec![];
+            write_rows(&rows, *format, &mut out).unwrap();
+            let read = read_rows(out.as_slice(), *format, "crvCOMP").unwrap();
+            assert_eq!(read.len(), 2);
+            assert_eq!(read[1].timestamp, 200);
+            assert_eq!(read[1].usd_all, "12");
+            assert_eq!(read[1].shares, None);
+            assert_eq!(read[1].coins, rows[1].entry.coins);
+        }
+    }
+
+    #[test]
+    fn test_read_rows_rejects_invalid_decimal() {
+        let csv = "timestamp,price_per_share,usd_1,usd_2,usd_all\n100,1.01,10,20,thirty\n";
+        assert!(read_rows(csv.as_bytes(), ExportFormat::Csv, "crvCOMP").is_err());
+    }
+
+    #[test]
+    fn test_read_rows_with_two_coin_values() {
+        let csv = "timestamp,price_per_share,usd_1,usd_2,usd_all\n100,1.01,10,20,30\n";
+        let read = read_rows(csv.as_bytes(), ExportFormat::Csv, "crvCOMP").unwrap();
+        let symbols: Vec<&str> = read[0]
+            .coins
+            .iter()
+            .map(|coin| coin.symbol.as_str())
+            .collect();
+        assert_eq!(symbols, vec!["DAI", "USDC"]);
+        assert_eq!(read[0].coins[1].usd, "20");
+    }
+}
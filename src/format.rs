@@ -1,18 +1,82 @@
+use anyhow::Result;
 use bigdecimal::BigDecimal;
+use serde::Serialize;
 
-use crate::types::{UserVaultHoldings, VaultPerformance};
+use crate::types::{SnapshotBlock, UserVaultHoldings, VaultPerformance, VaultPosition, Window};
 
 use chrono::Local;
 
-pub fn print_header() {
+/// Clear the terminal and move the cursor to the top left, to redraw the report in place
+pub fn clear_screen() {
+    print!("\x1B[2J\x1B[H");
+}
+
+const WINDOW_COLUMN_WIDTH: usize = 26;
+
+fn separator_line(windows: usize) -> String {
+    format!(
+        "------------|-------------|-----------|------------|{}",
+        format!("{}|", "-".repeat(WINDOW_COLUMN_WIDTH)).repeat(windows)
+    )
+}
+
+fn window_cells(performance: &VaultPerformance) -> String {
+    performance
+        .windows
+        .iter()
+        .map(|window| {
+            format!(
+                "{:8.2} ({}/{} %)|",
+                window.gain,
+                rate_cell(&window.apr),
+                rate_cell(&window.apy)
+            )
+        })
+        .collect()
+}
+
+fn rate_cell(rate: &Option<BigDecimal>) -> String {
+    match rate {
+        Some(rate) => format!("{:6.2}", rate),
+        None => format!("{:>6}", "n/a"),
+    }
+}
+
+pub fn print_header(windows: &[Window]) {
+    let separator = separator_line(windows.len());
+    // The title spans the last check and window columns. Both emojis are two columns wide.
+    let title = format!(
+        "🚜 Gain (USD) (APR / APY) 📈 @ {:27}",
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    let title_width = 12 + (WINDOW_COLUMN_WIDTH + 1) * windows.len();
+    let padding = title_width.saturating_sub(title.chars().count() + 2);
+    let window_titles: String = windows
+        .iter()
+        .map(|window| {
+            format!(
+                "{:^width$}|",
+                format!("past {}", window.label),
+                width = WINDOW_COLUMN_WIDTH
+            )
+        })
+        .collect();
+
     print!(
-"
---------------------------------------------------------------------------------------------------------------------------------
-            |             |           |                                    🚜 Gain (USD) / APY 📈 @ {:27}|
-------------|-------------|-----------|------------|------------------|------------------|------------------|------------------|
-VAULT       |Price / share| USD value | last check |     past hour    |     past day     |     past week    |    past month    |
-------------|-------------|-----------|------------|------------------|------------------|------------------|------------------|
-", Local::now().format("%Y-%m-%d %H:%M:%S"));
+        "
+{}
+            |             |           |{}{}|
+{}
+VAULT       | PPS / Amount| USD value | last check |{}
+{}
+",
+        "-".repeat(separator.len()),
+        " ".repeat(padding),
+        title,
+        separator,
+        window_titles,
+        separator
+    );
 }
 
 pub fn print_result(
@@ -21,37 +85,104 @@ pub fn print_result(
     performance: &VaultPerformance,
 ) {
     print!(
-"
-{:12}|{:10.4}   |{:11.2}|{:12.2}|{:8.2} ({:5.2} %)|{:8.2} ({:5.2} %)|{:8.2} ({:5.2} %)|{:8.2} ({:5.2} %)|
-------------|-------------|-----------|------------|------------------|------------------|------------------|------------------|
+        "
+{:12}|{:10.4}   |{:11.2}|{:12.2}|{}
 ",
-vault_name,
-current_holdings.price_per_share,
-current_holdings.usd_all,
-performance.gain_last_check,
-performance.gain_past_hour,
-performance.apy_past_hour,
-performance.gain_past_day,
-performance.apy_past_day,
-performance.gain_past_week,
-performance.apy_past_week,
-performance.gain_past_month,
-performance.apy_past_month,
-);
-}
-
-pub fn print_footer(total: BigDecimal, performance: &VaultPerformance) {
+        vault_name,
+        current_holdings.price_per_share,
+        current_holdings.usd_all,
+        performance.gain_last_check,
+        window_cells(performance),
+    );
+    // One row per coin of the pool, below the price per share and value of the vault
+    let blank_windows =
+        format!("{}|", " ".repeat(WINDOW_COLUMN_WIDTH)).repeat(performance.windows.len());
+    for coin in &current_holdings.coins {
+        println!(
+            "  {:10}|{:13.2}|{:11.2}|            |{}",
+            coin.symbol, coin.amount, coin.usd, blank_windows
+        );
+    }
+    println!("{}", separator_line(performance.windows.len()));
+}
+
+pub fn print_footer(label: &str, total: BigDecimal, performance: &VaultPerformance) {
     print!(
-"
-TOTAL       |             |{:11.2}|{:12.2}|{:8.2}          |{:8.2}          |{:8.2}          |{:8.2}          |
-------------|-------------|-----------|------------|------------------|------------------|------------------|------------------|
+        "
+{:12}|             |{:11.2}|{:12.2}|{}
+{}
 ",
+        label,
+        total,
+        performance.gain_last_check,
+        window_cells(performance),
+        separator_line(performance.windows.len())
+    );
+}
+
+pub fn print_positions(positions: &[VaultPosition]) {
+    print!(
+"
+---------------------------------------------------------------------------------------------------------------
+VAULT                |ADDRESS                                   |      Shares      |Price / share|   Tokens    |
+---------------------|------------------------------------------|------------------|-------------|-------------|
+");
+    for position in positions {
+        println!(
+            "{:21}|{:42}|{:18.4}|{:10.4}   |{:13.2}|",
+            position.symbol,
+            format!("{:?}", position.vault_address),
+            position.shares,
+            position.price_per_share,
+            &position.shares * &position.price_per_share,
+        );
+    }
+    println!("---------------------|------------------------------------------|------------------|-------------|-------------|");
+}
+
+#[derive(Serialize)]
+pub struct JsonVaultReport<'a> {
+    pub vault: &'a str,
+    pub holdings: &'a UserVaultHoldings,
+    pub performance: &'a VaultPerformance,
+}
+
+#[derive(Serialize)]
+pub struct JsonTotal {
+    pub usd_all: BigDecimal,
+    pub performance: VaultPerformance,
+}
+
+#[derive(Serialize)]
+pub struct JsonHolderReport<'a> {
+    /// The label of the holder in the config file, or its address
+    pub holder: &'a str,
+    pub address: &'a str,
+    pub vaults: Vec<JsonVaultReport<'a>>,
+    pub total: JsonTotal,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    block_number: u64,
+    timestamp: u64,
+    holders: Vec<JsonHolderReport<'a>>,
+    total: JsonTotal,
+}
 
-total,
-performance.gain_last_check,
-performance.gain_past_hour,
-performance.gain_past_day,
-performance.gain_past_week,
-performance.gain_past_month,
-);
+/// Print the performance report as JSON. Decimals are written as strings to keep their
+/// precision.
+pub fn print_json_report(
+    snapshot_block: &SnapshotBlock,
+    holders: Vec<JsonHolderReport>,
+    total: JsonTotal,
+) -> Result<()> {
+    let report = JsonReport {
+        block_number: snapshot_block.number,
+        timestamp: snapshot_block.timestamp,
+        holders,
+        total,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
 }
@@ -1,38 +1,79 @@
 #[macro_use]
 extern crate derivative;
+#[macro_use]
+extern crate clap;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bigdecimal::BigDecimal;
 use calculations::get_cumulated_performance;
-use chrono::NaiveDateTime;
-use clap::{App, Arg};
+use chrono::{Local, NaiveDate};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use ethers::prelude::*;
+use futures::future::try_join_all;
 use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::net::SocketAddr;
+use std::process;
+use std::slice;
+use std::sync::Arc;
+use std::time::Duration;
 use types::{UserVaultHoldings, VaultPerformance};
 
+mod backfill;
+mod blocks;
 mod calculations;
+mod config;
 mod constants;
 mod contracts;
+mod error;
+mod export;
 mod format;
+mod metrics;
+mod oracle;
+mod provider;
 mod store;
 mod types;
 mod utils;
 mod vaults;
 
-use crate::calculations::get_performance;
-use crate::format::{print_footer, print_header, print_result};
-use crate::store::{db_info, init_default_db, read_entries, save_entry};
-use crate::types::VaultIdentifier;
-use crate::vaults::{get_crvcomp_holdings, get_crvsaave_holdings};
+use crate::backfill::backfill;
+use crate::blocks::get_latest_snapshot_block;
+use crate::calculations::{gain_between, get_performance};
+use crate::config::{get_config_path, load_default_config, Config, HolderConfig};
+use crate::constants::DAY_IN_SEC;
+use crate::error::{exit_code, NumbaError};
+use crate::export::{export_rows, read_rows, write_rows, ExportFormat};
+use crate::format::{
+    clear_screen, print_footer, print_header, print_json_report, print_positions, print_result,
+    JsonHolderReport, JsonTotal, JsonVaultReport,
+};
+use crate::metrics::VaultMetrics;
+use crate::provider::{
+    connect, is_ens_name, resolve_ens_name, resolve_rpc_endpoint, RPC_URL_ENV_VAR,
+};
+use crate::store::{open_default_store, SnapshotStore};
+use crate::types::{OutputFormat, ReturnMethod, SnapshotBlock, VaultIdentifier, Window};
+use crate::utils::{checksum_address, date_time, parse_duration};
+use crate::vaults::{
+    default_vaults, discover_positions, get_holdings, vaults_by_name, VaultAdapter,
+};
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {:?}", err);
+        process::exit(exit_code(&err));
+    }
+}
+
+async fn run() -> Result<()> {
     let app = App::new("NumbaGoUp")
         .about("Track the holdings of your yearn crvCOMP+crvSAAVE vault go up in USD")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("holder-address")
-                .help("The address of the vault holder")
-                .required(true)
+                .help("The addresses of the vault holders or their labels in the config file")
+                .multiple(true)
                 .index(1),
         )
         .arg(
@@ -40,29 +81,578 @@ async fn main() -> Result<()> {
                 .long("db-info")
                 .takes_value(false)
                 .help("Show db info"),
+        )
+        .arg(
+            Arg::with_name("discover")
+                .long("discover")
+                .takes_value(false)
+                .help("List every yearn v2 vault in which the holder owns shares"),
+        )
+        .arg(
+            Arg::with_name("return-method")
+                .long("return-method")
+                .takes_value(true)
+                .possible_values(&["twr", "mwr"])
+                .help("Show time-weighted (twr) or money-weighted (mwr) returns as APR / APY"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["table", "json"])
+                .help("Print the report as a table or as JSON"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .takes_value(true)
+                .value_name("INTERVAL")
+                .conflicts_with_all(&["db-info", "discover"])
+                .help("Keep running and take a snapshot every interval, e.g. 30s, 5m or 1h"),
+        )
+        .arg(
+            Arg::with_name("rpc-url")
+                .long("rpc-url")
+                .takes_value(true)
+                .value_name("URL")
+                .global(true)
+                .help("The Ethereum JSON-RPC endpoint (overrides NUMBAGOUP_RPC_URL and the config file)"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .takes_value(true)
+                .possible_values(&["sqlite", "ron"])
+                .global(true)
+                .help("Where snapshots are stored (overrides the config file, defaults to sqlite)"),
+        )
+        .subcommand(
+            SubCommand::with_name("backfill")
+                .about("Rebuild past snapshots from an archive node")
+                .arg(
+                    Arg::with_name("holder-address")
+                        .help("The address of the vault holder or its label in the config file")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .required(true)
+                        .help("The date (UTC) of the first snapshot"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .help("The date (UTC) of the last snapshot, defaults to now"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("1d")
+                        .help("The time between two snapshots, e.g. 6h, 1d or 1w"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Write the stored snapshots of a vault as CSV or JSON")
+                .arg(
+                    Arg::with_name("holder-address")
+                        .help("The address of the vault holder or its label in the config file")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("vault")
+                        .long("vault")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The name of the vault, e.g. crvCOMP"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["csv", "json"])
+                        .default_value("csv")
+                        .help("The output format"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .help("Only export snapshots taken on or after this date (UTC)"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .help("Only export snapshots taken on or before this date (UTC)"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write to a file instead of stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Merge snapshots from a CSV or JSON file into the store")
+                // The holder can be left out if the config file lists a single one
+                .setting(AppSettings::AllowMissingPositional)
+                .arg(
+                    Arg::with_name("holder-address")
+                        .help("The address of the vault holder or its label in the config file")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("The file to import, as written by the export subcommand")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("vault")
+                        .long("vault")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The name of the vault, e.g. crvCOMP"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["csv", "json"])
+                        .default_value("csv")
+                        .help("The input format"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve-metrics")
+                .about("Take snapshots periodically and serve them as Prometheus metrics")
+                .arg(
+                    Arg::with_name("holder-address")
+                        .help("The addresses of the vault holders or their labels in the config file")
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .value_name("ADDRESS")
+                        .default_value("127.0.0.1:9898")
+                        .help("The address to serve /metrics on"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("5m")
+                        .help("The time between two snapshots, e.g. 30s, 5m or 1h"),
+                )
+                .arg(
+                    Arg::with_name("return-method")
+                        .long("return-method")
+                        .takes_value(true)
+                        .possible_values(&["twr", "mwr"])
+                        .help("Export time-weighted (twr) or money-weighted (mwr) returns as APR / APY"),
+                ),
         );
 
     let matches = app.get_matches();
-    if let Some(address) = matches.value_of("holder-address") {
-        let comp_id = VaultIdentifier::new(address, "crvCOMP");
-        let saave_id = VaultIdentifier::new(address, "crvSAAVE");
-
-        if matches.is_present("db-info") {
-            show_db_info(&comp_id.id())?;
-            show_db_info(&saave_id.id())?;
-        } else {
-            performance_report(&comp_id, &saave_id).await?;
+    let config = load_default_config()?;
+    if let Some(backfill_matches) = matches.subcommand_matches("backfill") {
+        return run_backfill(backfill_matches, &config).await;
+    }
+    if let Some(export_matches) = matches.subcommand_matches("export") {
+        return run_export(export_matches, &config).await;
+    }
+    if let Some(import_matches) = matches.subcommand_matches("import") {
+        return run_import(import_matches, &config).await;
+    }
+    if let Some(metrics_matches) = matches.subcommand_matches("serve-metrics") {
+        return run_serve_metrics(metrics_matches, &config).await;
+    }
+
+    let mut holders = resolve_holders(&matches, &config)?;
+    let provider = resolve_addresses(&matches, &config, &mut holders).await?;
+    if matches.is_present("db-info") {
+        let store = open_store(&matches, &config)?;
+        for holder in &holders {
+            for vault in &holder.vaults {
+                show_db_info(
+                    store.as_ref(),
+                    &VaultIdentifier::new(&holder.address, vault.name()).id(),
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    let provider = match provider {
+        Some(provider) => provider,
+        None => connect_provider(&matches, &config).await?,
+    };
+    if matches.is_present("discover") {
+        let snapshot_block = get_latest_snapshot_block(&provider).await?;
+        for holder in &holders {
+            if holders.len() > 1 {
+                println!("\n{}", holder.name());
+            }
+            print_positions(
+                &discover_positions(&provider, &holder.address, &snapshot_block).await?,
+            );
+        }
+        return Ok(());
+    }
+
+    let options = report_options(&matches, &config)?;
+    let store = open_store(&matches, &config)?;
+    match matches.value_of("watch") {
+        Some(interval) => {
+            let interval = parse_duration(interval).map_err(|err| anyhow::anyhow!(err))?;
+            watch(&provider, store.as_ref(), &holders, &options, interval).await;
+        }
+        None => {
+            let report = collect_report(&provider, store.as_ref(), &holders, &options).await?;
+            print_report(&holders, &report, &options)?;
+        }
+    }
+    Ok(())
+}
+
+/// A holder address together with the vaults to track for it
+struct Holder {
+    label: Option<String>,
+    address: String,
+    vaults: Vec<Box<dyn VaultAdapter>>,
+}
+
+impl Holder {
+    fn from_config(holder: &HolderConfig) -> Holder {
+        Holder {
+            label: Some(holder.label.clone()),
+            address: holder.address.clone(),
+            vaults: match &holder.vaults {
+                Some(names) => vaults_by_name(names),
+                None => default_vaults(),
+            },
+        }
+    }
+
+    /// The label of the holder, or its address if it has none
+    fn name(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.address)
+    }
+}
+
+/// Resolve a holder address or a label of the config file. Addresses that are listed in the
+/// config file get the vaults configured for them.
+fn resolve_holder_value(value: &str, config: &Config) -> Holder {
+    let holder_config = config.holder(value).or_else(|| {
+        config
+            .holders
+            .iter()
+            .find(|holder| holder.address.eq_ignore_ascii_case(value))
+    });
+    match holder_config {
+        Some(holder) => Holder::from_config(holder),
+        None => Holder {
+            label: None,
+            address: value.to_owned(),
+            vaults: default_vaults(),
+        },
+    }
+}
+
+/// Resolve a single holder. Without a value, the config file has to list exactly one holder.
+fn resolve_holder(value: Option<&str>, config: &Config) -> Result<Holder> {
+    match value {
+        Some(value) => Ok(resolve_holder_value(value, config)),
+        None if config.holders.len() == 1 => Ok(Holder::from_config(&config.holders[0])),
+        None if config.holders.is_empty() => Err(NumbaError::Config(
+            "No holders are configured, pass a holder address".to_owned(),
+        )
+        .into()),
+        None => Err(NumbaError::Config(format!(
+            "Several holders are configured, pass a holder address or one of their labels: {}",
+            config
+                .holders
+                .iter()
+                .map(|holder| holder.label.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+        .into()),
+    }
+}
+
+/// Resolve several holders. Without values, all holders of the config file are used.
+fn resolve_holders(matches: &ArgMatches<'_>, config: &Config) -> Result<Vec<Holder>> {
+    match matches.values_of("holder-address") {
+        Some(values) => Ok(values
+            .map(|value| resolve_holder_value(value, config))
+            .collect()),
+        None if config.holders.is_empty() => Err(NumbaError::Config(
+            "No holders are configured, pass a holder address".to_owned(),
+        )
+        .into()),
+        None => Ok(config.holders.iter().map(Holder::from_config).collect()),
+    }
+}
+
+/// How the performance of the vaults is calculated and printed
+struct ReportOptions {
+    method: ReturnMethod,
+    output: OutputFormat,
+    windows: Vec<Window>,
+}
+
+/// Take the options from the flags, then from the config file
+fn report_options(matches: &ArgMatches<'_>, config: &Config) -> Result<ReportOptions> {
+    Ok(ReportOptions {
+        method: match matches.value_of("return-method") {
+            Some(value) => value.parse().map_err(|err: String| anyhow::anyhow!(err))?,
+            None => config.output.return_method.unwrap_or_default(),
+        },
+        output: match matches.value_of("output") {
+            Some(value) => value.parse().map_err(|err: String| anyhow::anyhow!(err))?,
+            None => config.output.format.unwrap_or_default(),
+        },
+        windows: config.windows(),
+    })
+}
+
+/// Replace the address of every holder by its checksum address, resolving ENS names through the
+/// provider. The provider is only connected if there is a name to resolve, and is returned for
+/// reuse.
+async fn resolve_addresses(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    holders: &mut [Holder],
+) -> Result<Option<Provider<Http>>> {
+    let mut provider = None;
+    for holder in holders.iter_mut() {
+        if let Some(address) = checksum_address(&holder.address) {
+            holder.address = address;
+            continue;
+        }
+        if !is_ens_name(&holder.address) {
+            return Err(NumbaError::InvalidAddress(format!(
+                "'{}' is neither a hex address nor an ENS name",
+                holder.address
+            ))
+            .into());
         }
+        if provider.is_none() {
+            provider = Some(connect_provider(matches, config).await?);
+        }
+        if let Some(provider) = &provider {
+            let address = resolve_ens_name(provider, &holder.address).await?;
+            let name = std::mem::replace(&mut holder.address, address);
+            holder.label.get_or_insert(name);
+        }
+    }
+    Ok(provider)
+}
+
+async fn connect_provider(matches: &ArgMatches<'_>, config: &Config) -> Result<Provider<Http>> {
+    let endpoint = resolve_rpc_endpoint(
+        matches.value_of("rpc-url"),
+        std::env::var(RPC_URL_ENV_VAR).ok(),
+        config,
+        get_config_path()?,
+    )?;
+    connect(&endpoint).await
+}
+
+fn open_store(matches: &ArgMatches<'_>, config: &Config) -> Result<Box<dyn SnapshotStore>> {
+    let backend = match matches.value_of("store") {
+        Some(value) => value.parse().map_err(|err: String| anyhow::anyhow!(err))?,
+        None => config.store.unwrap_or_default(),
+    };
+    open_default_store(backend)
+}
+
+fn parse_date(value: &str) -> Result<u64> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|err| anyhow::anyhow!("Invalid date '{}': {}", value, err))?;
+    Ok(date.and_hms(0, 0, 0).timestamp().try_into()?)
+}
+
+async fn run_backfill(matches: &ArgMatches<'_>, config: &Config) -> Result<()> {
+    let mut holder = resolve_holder(matches.value_of("holder-address"), config)?;
+    let start = parse_date(matches.value_of("from").unwrap())?;
+    let end = matches.value_of("to").map(parse_date).transpose()?;
+    let interval = parse_duration(matches.value_of("interval").unwrap())
+        .map_err(|err| anyhow::anyhow!(err))?;
+
+    let provider = match resolve_addresses(matches, config, slice::from_mut(&mut holder)).await? {
+        Some(provider) => provider,
+        None => connect_provider(matches, config).await?,
+    };
+    let store = open_store(matches, config)?;
+    let results = backfill(
+        &provider,
+        store.as_ref(),
+        &holder.address,
+        &holder.vaults,
+        start,
+        end,
+        interval,
+    )
+    .await?;
+
+    for (vault_name, stats) in results {
+        println!(
+            "{}: {} entries added, {} skipped",
+            vault_name, stats.added, stats.skipped
+        );
+    }
+    Ok(())
+}
+
+async fn run_export(matches: &ArgMatches<'_>, config: &Config) -> Result<()> {
+    let mut holder = resolve_holder(matches.value_of("holder-address"), config)?;
+    resolve_addresses(matches, config, slice::from_mut(&mut holder)).await?;
+    let vault_name = matches.value_of("vault").unwrap();
+    let format = value_t!(matches, "format", ExportFormat)?;
+    let from = matches.value_of("from").map(parse_date).transpose()?;
+    // Include every snapshot of the last day
+    let to = matches
+        .value_of("to")
+        .map(parse_date)
+        .transpose()?
+        .map(|to| to + DAY_IN_SEC - 1);
+    ensure_known_vault(vault_name)?;
+
+    let store = open_store(matches, config)?;
+    let entries = store.read_entries(&VaultIdentifier::new(&holder.address, vault_name).id())?;
+    let rows = export_rows(&entries, from, to);
+
+    match matches.value_of("output") {
+        Some(path) => write_rows(&rows, format, File::create(path)?)?,
+        None => write_rows(&rows, format, std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+async fn run_import(matches: &ArgMatches<'_>, config: &Config) -> Result<()> {
+    let mut holder = resolve_holder(matches.value_of("holder-address"), config)?;
+    resolve_addresses(matches, config, slice::from_mut(&mut holder)).await?;
+    let vault_name = matches.value_of("vault").unwrap();
+    let format = value_t!(matches, "format", ExportFormat)?;
+    let path = matches.value_of("file").unwrap();
+    ensure_known_vault(vault_name)?;
+
+    let file = File::open(path).with_context(|| format!("Can't open {}", path))?;
+    let rows =
+        read_rows(file, format, vault_name).with_context(|| format!("Can't import {}", path))?;
+    let entries = rows
+        .iter()
+        .map(UserVaultHoldings::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let store = open_store(matches, config)?;
+    let stats = store.merge_entries(
+        &VaultIdentifier::new(&holder.address, vault_name).id(),
+        &entries,
+    )?;
+    println!(
+        "{}: {} entries added, {} skipped",
+        vault_name, stats.added, stats.skipped
+    );
+    Ok(())
+}
+
+async fn run_serve_metrics(matches: &ArgMatches<'_>, config: &Config) -> Result<()> {
+    let mut holders = resolve_holders(matches, config)?;
+    let listen: SocketAddr = value_t!(matches, "listen", SocketAddr)?;
+    let interval = parse_duration(matches.value_of("interval").unwrap())
+        .map_err(|err| anyhow::anyhow!(err))?;
+    let options = report_options(matches, config)?;
+
+    let provider = match resolve_addresses(matches, config, &mut holders).await? {
+        Some(provider) => provider,
+        None => connect_provider(matches, config).await?,
+    };
+    let store = open_store(matches, config)?;
+
+    let metrics = Arc::new(VaultMetrics::new()?);
+    println!("Serving metrics on http://{}/metrics", listen);
+
+    let refresh = refresh_metrics(
+        &provider,
+        store.as_ref(),
+        &holders,
+        &options,
+        &metrics,
+        interval,
+    );
+    // The refresh loop never ends, so this only returns if the server fails
+    tokio::select! {
+        result = metrics::serve(listen, metrics.clone()) => result,
+        _ = refresh => Ok(()),
+    }
+}
+
+/// Take a snapshot every `interval_sec` seconds and update the metrics with it
+async fn refresh_metrics(
+    provider: &Provider<Http>,
+    store: &dyn SnapshotStore,
+    holders: &[Holder],
+    options: &ReportOptions,
+    metrics: &VaultMetrics,
+    interval_sec: u64,
+) {
+    loop {
+        match collect_report(provider, store, holders, options).await {
+            Ok(report) => {
+                for (holder, holder_report) in holders.iter().zip(&report.holders) {
+                    for ((vault, holdings), performance) in holder
+                        .vaults
+                        .iter()
+                        .zip(&holder_report.holdings)
+                        .zip(&holder_report.performance)
+                    {
+                        metrics.update(&holder.address, vault.name(), holdings, performance);
+                    }
+                }
+            }
+            // Keep serving the last snapshot and try again on the next tick
+            Err(err) => eprintln!("Can't take snapshot: {:#}", err),
+        }
+        tokio::time::sleep(Duration::from_secs(interval_sec)).await;
     }
+}
 
+fn ensure_known_vault(vault_name: &str) -> Result<()> {
+    if !default_vaults()
+        .iter()
+        .any(|vault| vault.name() == vault_name)
+    {
+        anyhow::bail!("Unknown vault '{}'", vault_name);
+    }
     Ok(())
 }
 
-fn show_db_info(group_id: &str) -> Result<()> {
-    let db = init_default_db().map_err(|err| anyhow::anyhow!(err))?;
-    let info = db_info(&db, group_id);
-    let oldest = NaiveDateTime::from_timestamp(info.oldest_timestamp.try_into().unwrap(), 0);
-    let newest = NaiveDateTime::from_timestamp(info.newest_timestamp.try_into().unwrap(), 0);
+fn show_db_info(store: &dyn SnapshotStore, group_id: &str) -> Result<()> {
+    let info = store.db_info(group_id)?;
+    let stored_date_time = |timestamp| {
+        date_time(timestamp)
+            .ok_or_else(|| NumbaError::CorruptRow(format!("Invalid timestamp {}", timestamp)))
+    };
+    let oldest = stored_date_time(info.oldest_timestamp)?;
+    let newest = stored_date_time(info.newest_timestamp)?;
 
     print!(
         "
@@ -75,49 +665,208 @@ Newest: {}
     Ok(())
 }
 
-async fn performance_report(comp_id: &VaultIdentifier, saave_id: &VaultIdentifier) -> Result<()> {
-    let holder_address = &comp_id.address;
-    let provider = Provider::<Http>::try_from(
-        "https://mainnet.infura.io/v3/c60b0bb42f8a4c6481ecd229eddaca27",
-    )?;
+/// The holdings and performance of each vault of a holder
+struct HolderReport {
+    holdings: Vec<UserVaultHoldings>,
+    performance: Vec<VaultPerformance>,
+}
+
+/// The reports of all holders at the same block
+struct Report {
+    snapshot_block: SnapshotBlock,
+    holders: Vec<HolderReport>,
+}
 
-    let comp_holdings = get_crvcomp_holdings(&provider, &holder_address).await?;
-    let saave_holdings = get_crvsaave_holdings(&provider, &holder_address).await?;
+/// Read the holdings of every vault of the holder concurrently
+async fn fetch_holdings(
+    provider: &Provider<Http>,
+    holder: &Holder,
+    snapshot_block: &SnapshotBlock,
+) -> Result<Vec<UserVaultHoldings>> {
+    try_join_all(
+        holder
+            .vaults
+            .iter()
+            .map(|vault| get_holdings(provider, vault.as_ref(), &holder.address, snapshot_block)),
+    )
+    .await
+}
 
-    print_header();
+/// Take a snapshot of every vault of every holder, save it and calculate the performance of
+/// each vault. The holders are fetched concurrently.
+async fn collect_report(
+    provider: &Provider<Http>,
+    store: &dyn SnapshotStore,
+    holders: &[Holder],
+    options: &ReportOptions,
+) -> Result<Report> {
+    let snapshot_block = get_latest_snapshot_block(provider).await?;
 
-    let comp_performance = display_holdings(&comp_id, &comp_holdings)?;
-    let saave_performance = display_holdings(&saave_id, &saave_holdings)?;
+    let all_holdings = try_join_all(
+        holders
+            .iter()
+            .map(|holder| fetch_holdings(provider, holder, &snapshot_block)),
+    )
+    .await?;
 
-    let (total, total_performance) = get_cumulated_performance(
-        &vec![comp_holdings, saave_holdings],
-        &vec![comp_performance, saave_performance],
+    let mut reports = Vec::with_capacity(holders.len());
+    for (holder, holdings) in holders.iter().zip(all_holdings) {
+        let performance = holder
+            .vaults
+            .iter()
+            .zip(&holdings)
+            .map(|(vault, holdings)| {
+                let id = VaultIdentifier::new(&holder.address, vault.name());
+                record_holdings(store, &id, holdings, options)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        reports.push(HolderReport {
+            holdings,
+            performance,
+        });
+    }
+
+    Ok(Report {
+        snapshot_block,
+        holders: reports,
+    })
+}
+
+fn print_report(holders: &[Holder], report: &Report, options: &ReportOptions) -> Result<()> {
+    let (grand_total, grand_total_performance) = get_cumulated_performance(
+        &report
+            .holders
+            .iter()
+            .flat_map(|holder| holder.holdings.iter().cloned())
+            .collect::<Vec<_>>(),
+        &report
+            .holders
+            .iter()
+            .flat_map(|holder| holder.performance.iter().cloned())
+            .collect::<Vec<_>>(),
     );
-    print_footer(total, &total_performance);
+
+    match options.output {
+        OutputFormat::Table => {
+            for (holder, holder_report) in holders.iter().zip(&report.holders) {
+                if holders.len() > 1 {
+                    println!("\n{} ({})", holder.name(), holder.address);
+                }
+                print_header(&options.windows);
+                for ((vault, holdings), performance) in holder
+                    .vaults
+                    .iter()
+                    .zip(&holder_report.holdings)
+                    .zip(&holder_report.performance)
+                {
+                    print_result(vault.name(), holdings, performance);
+                }
+                let (total, total_performance) =
+                    get_cumulated_performance(&holder_report.holdings, &holder_report.performance);
+                print_footer("TOTAL", total, &total_performance);
+            }
+            if holders.len() > 1 {
+                println!("\nAll holders");
+                print_header(&options.windows);
+                print_footer("GRAND TOTAL", grand_total, &grand_total_performance);
+            }
+        }
+        OutputFormat::Json => {
+            let holder_reports = holders
+                .iter()
+                .zip(&report.holders)
+                .map(|(holder, holder_report)| {
+                    let (total, total_performance) = get_cumulated_performance(
+                        &holder_report.holdings,
+                        &holder_report.performance,
+                    );
+                    JsonHolderReport {
+                        holder: holder.name(),
+                        address: &holder.address,
+                        vaults: holder
+                            .vaults
+                            .iter()
+                            .zip(&holder_report.holdings)
+                            .zip(&holder_report.performance)
+                            .map(|((vault, holdings), performance)| JsonVaultReport {
+                                vault: vault.name(),
+                                holdings,
+                                performance,
+                            })
+                            .collect(),
+                        total: JsonTotal {
+                            usd_all: total,
+                            performance: total_performance,
+                        },
+                    }
+                })
+                .collect();
+            print_json_report(
+                &report.snapshot_block,
+                holder_reports,
+                JsonTotal {
+                    usd_all: grand_total,
+                    performance: grand_total_performance,
+                },
+            )?;
+        }
+    }
     Ok(())
 }
 
-fn display_holdings(
+/// Take a snapshot every `interval_sec` seconds and redraw the report. Failed snapshots are
+/// logged and retried on the next tick.
+async fn watch(
+    provider: &Provider<Http>,
+    store: &dyn SnapshotStore,
+    holders: &[Holder],
+    options: &ReportOptions,
+    interval_sec: u64,
+) {
+    loop {
+        let result = collect_report(provider, store, holders, options)
+            .await
+            .and_then(|report| {
+                if options.output == OutputFormat::Table {
+                    clear_screen();
+                }
+                print_report(holders, &report, options)
+            });
+        if let Err(err) = result {
+            eprintln!(
+                "{} Can't take snapshot, retrying in {}s: {:#}",
+                Local::now().format("%Y-%m-%d %H:%M:%S"),
+                interval_sec,
+                err
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(interval_sec)).await;
+    }
+}
+
+/// Save the holdings and calculate the performance of the vault including them
+fn record_holdings(
+    store: &dyn SnapshotStore,
     group_id: &VaultIdentifier,
     holdings: &UserVaultHoldings,
+    options: &ReportOptions,
 ) -> Result<VaultPerformance> {
     let id = &group_id.id();
-    let db = init_default_db().map_err(|err| anyhow::anyhow!(err))?;
-
-    let previous_entries = read_entries(&db, id);
+    let previous_entries = store.read_entries(id)?;
 
     let gain = previous_entries
         .last()
-        .map(|previous| &holdings.usd_all - &previous.usd_all)
+        .map(|previous| gain_between(previous, holdings))
         .unwrap_or_else(|| BigDecimal::from(0));
 
-    save_entry(&db, id, &holdings)?;
-
-    let latest_entries = read_entries(&db, id);
-
-    let performance = get_performance(gain, &latest_entries);
+    store.save_entry(id, holdings)?;
 
-    print_result(&group_id.vault_name, &holdings, &performance);
+    let latest_entries = store.read_entries(id)?;
 
-    Ok(performance)
+    Ok(get_performance(
+        gain,
+        &latest_entries,
+        options.method,
+        &options.windows,
+    ))
 }
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, GaugeVec, Opts, Registry, TextEncoder};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::types::{UserVaultHoldings, VaultPerformance};
+
+/// Gauges of the latest snapshot and performance of each vault, labeled by holder and vault
+pub struct VaultMetrics {
+    registry: Registry,
+    usd_all: GaugeVec,
+    coin_amount: GaugeVec,
+    coin_usd: GaugeVec,
+    price_per_share: GaugeVec,
+    gain: GaugeVec,
+    apr: GaugeVec,
+    apy: GaugeVec,
+}
+
+impl VaultMetrics {
+    pub fn new() -> Result<VaultMetrics> {
+        let registry = Registry::new_custom(Some("numbagoup".to_owned()), None)?;
+        let gauge = |name: &str, help: &str, labels: &[&str]| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let vault_labels = &["holder", "vault"];
+        let coin_labels = &["holder", "vault", "coin"];
+        let window_labels = &["holder", "vault", "window"];
+
+        Ok(VaultMetrics {
+            usd_all: gauge("usd_all", "USD value of the holdings", vault_labels)?,
+            coin_amount: gauge("coin_amount", "Amount of each coin", coin_labels)?,
+            coin_usd: gauge("coin_usd", "USD value of each coin", coin_labels)?,
+            price_per_share: gauge("price_per_share", "Price per vault share", vault_labels)?,
+            gain: gauge("gain_usd", "Gain in USD over the window", window_labels)?,
+            apr: gauge(
+                "apr_percent",
+                "APR in percent over the window",
+                window_labels,
+            )?,
+            apy: gauge(
+                "apy_percent",
+                "APY in percent over the window",
+                window_labels,
+            )?,
+            registry,
+        })
+    }
+
+    pub fn update(
+        &self,
+        holder_address: &str,
+        vault_name: &str,
+        holdings: &UserVaultHoldings,
+        performance: &VaultPerformance,
+    ) {
+        let labels = &[holder_address, vault_name];
+        self.usd_all
+            .with_label_values(labels)
+            .set(to_f64(&holdings.usd_all));
+        for coin in &holdings.coins {
+            let labels = &[holder_address, vault_name, &coin.symbol];
+            self.coin_amount
+                .with_label_values(labels)
+                .set(to_f64(&coin.amount));
+            self.coin_usd
+                .with_label_values(labels)
+                .set(to_f64(&coin.usd));
+        }
+        self.price_per_share
+            .with_label_values(labels)
+            .set(to_f64(&holdings.price_per_share));
+
+        self.gain
+            .with_label_values(&[holder_address, vault_name, "last_check"])
+            .set(to_f64(&performance.gain_last_check));
+        for window in &performance.windows {
+            let labels = &[holder_address, vault_name, &window.window];
+            self.gain
+                .with_label_values(labels)
+                .set(to_f64(&window.gain));
+            self.apr
+                .with_label_values(labels)
+                .set(window.apr.as_ref().map_or(f64::NAN, to_f64));
+            self.apy
+                .with_label_values(labels)
+                .set(window.apy.as_ref().map_or(f64::NAN, to_f64));
+        }
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+fn to_f64(value: &BigDecimal) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Serve the metrics on `GET /metrics` until the server fails
+pub async fn serve(address: SocketAddr, metrics: Arc<VaultMetrics>) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let metrics = metrics.clone();
+                async move { Ok::<_, Infallible>(respond(&metrics, request)) }
+            }))
+        }
+    });
+    Server::try_bind(&address)?.serve(make_service).await?;
+    Ok(())
+}
+
+fn respond(metrics: &VaultMetrics, request: Request<Body>) -> Response<Body> {
+    if request.uri().path() != "/metrics" {
+        return with_status(StatusCode::NOT_FOUND, "Not found");
+    }
+    match metrics.encode() {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(body))
+            .unwrap(),
+        Err(err) => with_status(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+}
+
+fn with_status(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message.to_owned()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::VaultMetrics;
+    use crate::types::{CoinHolding, UserVaultHoldings, VaultPerformance};
+    use bigdecimal::BigDecimal;
+
+    #[test]
+    fn test_encode_labels_by_holder_and_vault() {
+        let metrics = VaultMetrics::new().unwrap();
+        let mut holdings = UserVaultHoldings::zero();
+        holdings.usd_all = BigDecimal::from(1234);
+        holdings.coins.push(CoinHolding {
+            symbol: "DAI".to_owned(),
+            amount: BigDecimal::from(1000),
+            usd: BigDecimal::from(999),
+        });
+        let mut performance = VaultPerformance::zero();
+        performance.windows[2].apy = Some(BigDecimal::from(5));
+
+        metrics.update("0xdeadbeef", "crvCOMP", &holdings, &performance);
+
+        let text = metrics.encode().unwrap();
+        assert!(text.contains(r#"numbagoup_usd_all{holder="0xdeadbeef",vault="crvCOMP"} 1234"#));
+        assert!(text
+            .contains(r#"numbagoup_coin_usd{coin="DAI",holder="0xdeadbeef",vault="crvCOMP"} 999"#));
+        assert!(text.contains(
+            r#"numbagoup_apy_percent{holder="0xdeadbeef",vault="crvCOMP",window="week"} 5"#
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bigdecimal::BigDecimal;
+use ethers::prelude::*;
+use std::str::FromStr;
+
+use crate::constants::{CHAINLINK_AGGREGATOR_ABI, MAX_PRICE_AGE_SEC};
+use crate::contracts::{ChainlinkAggregator, RoundData};
+use crate::error::NumbaError;
+use crate::types::SnapshotBlock;
+use crate::utils::{u256_to_u64, Scale};
+
+/// A source for the USD price of a coin
+#[async_trait]
+pub trait PriceOracle {
+    /// The USD price of one whole coin as of the given block
+    async fn usd_price(&self, block: &SnapshotBlock) -> Result<BigDecimal>;
+}
+
+/// A Chainlink aggregator that quotes a coin in USD
+pub struct ChainlinkOracle {
+    address: String,
+    aggregator: ChainlinkAggregator,
+}
+
+impl ChainlinkOracle {
+    pub fn new(provider: &Provider<Http>, address: &str) -> Result<ChainlinkOracle> {
+        Ok(ChainlinkOracle {
+            address: address.to_owned(),
+            aggregator: ChainlinkAggregator::new(provider, CHAINLINK_AGGREGATOR_ABI, address)?,
+        })
+    }
+}
+
+#[async_trait]
+impl PriceOracle for ChainlinkOracle {
+    async fn usd_price(&self, block: &SnapshotBlock) -> Result<BigDecimal> {
+        let block_number = BlockNumber::from(block.number);
+        let (decimals, round) = futures::try_join!(
+            self.aggregator.decimals(block_number),
+            self.aggregator.latest_round_data(block_number)
+        )?;
+        Ok(price_from_round(&self.address, &round, decimals, block)?)
+    }
+}
+
+/// Turn the latest round of a feed into a price, rejecting non-positive and stale answers
+fn price_from_round(
+    feed: &str,
+    round: &RoundData,
+    decimals: u8,
+    block: &SnapshotBlock,
+) -> Result<BigDecimal, NumbaError> {
+    if round.answer <= I256::zero() {
+        return Err(NumbaError::UnexpectedResponse(format!(
+            "Price feed {} returned a non-positive price {}",
+            feed, round.answer
+        )));
+    }
+    let updated_at = u256_to_u64(
+        round.updated_at,
+        &format!("Update time of price feed {}", feed),
+    )?;
+    let age = block.timestamp.saturating_sub(updated_at);
+    if age > MAX_PRICE_AGE_SEC {
+        return Err(NumbaError::UnexpectedResponse(format!(
+            "Price feed {} is stale, its last update is {}s older than block {}",
+            feed, age, block.number
+        )));
+    }
+
+    let answer = BigDecimal::from_str(&round.answer.to_string())
+        .map_err(|err| NumbaError::UnexpectedResponse(err.to_string()))?;
+    Ok(answer.scale_by_decimals(decimals))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::DAY_IN_SEC;
+    use crate::contracts::RoundData;
+    use crate::oracle::price_from_round;
+    use crate::types::SnapshotBlock;
+    use bigdecimal::BigDecimal;
+    use ethers::prelude::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_price_from_round() {
+        let block = SnapshotBlock {
+            number: 100,
+            timestamp: 10 * DAY_IN_SEC,
+        };
+        let round = |answer: i64, updated_at: u64| RoundData {
+            answer: I256::from(answer),
+            updated_at: U256::from(updated_at),
+        };
+
+        let price = price_from_round("feed", &round(99_870_000, 9 * DAY_IN_SEC), 8, &block);
+        assert_eq!(price.unwrap(), BigDecimal::from_str("0.9987").unwrap());
+
+        let price = price_from_round("feed", &round(99_870_000, 9 * DAY_IN_SEC), 24, &block);
+        assert_eq!(
+            price.unwrap(),
+            BigDecimal::from_str("0.00000000000000009987").unwrap()
+        );
+
+        assert!(price_from_round("feed", &round(99_870_000, DAY_IN_SEC), 8, &block).is_err());
+        assert!(price_from_round("feed", &round(0, 9 * DAY_IN_SEC), 8, &block).is_err());
+    }
+}
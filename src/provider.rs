@@ -0,0 +1,168 @@
+use anyhow::Result;
+use ethers::prelude::*;
+use ethers::utils::to_checksum;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::constants::MAINNET_CHAIN_ID;
+use crate::error::NumbaError;
+
+pub const RPC_URL_ENV_VAR: &str = "NUMBAGOUP_RPC_URL";
+
+/// Where the RPC endpoint was configured
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcSource {
+    Flag,
+    Env,
+    ConfigFile(PathBuf),
+}
+
+impl fmt::Display for RpcSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcSource::Flag => write!(f, "--rpc-url flag"),
+            RpcSource::Env => write!(f, "{} environment variable", RPC_URL_ENV_VAR),
+            RpcSource::ConfigFile(path) => write!(f, "rpc_url in {}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcEndpoint {
+    pub url: String,
+    pub source: RpcSource,
+}
+
+/// Pick the RPC endpoint from the CLI flag, the environment or the config file, in that
+/// order of precedence.
+pub fn resolve_rpc_endpoint(
+    flag: Option<&str>,
+    env: Option<String>,
+    config: &Config,
+    config_path: PathBuf,
+) -> Result<RpcEndpoint> {
+    let endpoint = if let Some(url) = flag {
+        RpcEndpoint {
+            url: url.to_owned(),
+            source: RpcSource::Flag,
+        }
+    } else if let Some(url) = env {
+        RpcEndpoint {
+            url,
+            source: RpcSource::Env,
+        }
+    } else if let Some(url) = &config.rpc_url {
+        RpcEndpoint {
+            url: url.clone(),
+            source: RpcSource::ConfigFile(config_path),
+        }
+    } else {
+        return Err(NumbaError::Config(format!(
+            "No RPC endpoint configured. Pass --rpc-url, set {} or add rpc_url to {}",
+            RPC_URL_ENV_VAR,
+            config_path.display()
+        ))
+        .into());
+    };
+
+    if endpoint.url.trim().is_empty() {
+        return Err(
+            NumbaError::Config(format!("RPC endpoint from {} is empty", endpoint.source)).into(),
+        );
+    }
+    Ok(endpoint)
+}
+
+/// Create a provider for the given endpoint and make sure it is connected to mainnet.
+pub async fn connect(endpoint: &RpcEndpoint) -> Result<Provider<Http>> {
+    let provider = Provider::<Http>::try_from(endpoint.url.as_str()).map_err(|err| {
+        NumbaError::Config(format!(
+            "Invalid RPC URL '{}' from {}: {}",
+            endpoint.url, endpoint.source, err
+        ))
+    })?;
+
+    let chain_id = provider.get_chainid().await.map_err(|err| {
+        NumbaError::Rpc(format!(
+            "Can't reach RPC endpoint '{}' from {}: {}",
+            endpoint.url, endpoint.source, err
+        ))
+    })?;
+
+    if chain_id != U256::from(MAINNET_CHAIN_ID) {
+        return Err(NumbaError::Config(format!(
+            "RPC endpoint '{}' from {} is on chain {} but mainnet ({}) is required. \
+             Local forks need to be started with chain id {}",
+            endpoint.url, endpoint.source, chain_id, MAINNET_CHAIN_ID, MAINNET_CHAIN_ID
+        ))
+        .into());
+    }
+
+    Ok(provider)
+}
+
+/// Whether `value` looks like an ENS name such as `treasury.eth` rather than a hex address
+pub fn is_ens_name(value: &str) -> bool {
+    !value.starts_with("0x") && value.contains('.')
+}
+
+/// Resolve an ENS name through the ENS registry and return the checksum address it points to
+pub async fn resolve_ens_name(provider: &Provider<Http>, name: &str) -> Result<String> {
+    let address = provider
+        .resolve_name(name)
+        .await
+        .map_err(|err| NumbaError::Rpc(format!("Can't resolve ENS name '{}': {}", name, err)))?;
+    if address == Address::zero() {
+        return Err(NumbaError::InvalidAddress(format!(
+            "ENS name '{}' doesn't point to an address",
+            name
+        ))
+        .into());
+    }
+    Ok(to_checksum(&address, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::provider::{is_ens_name, resolve_rpc_endpoint, RpcSource};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_rpc_endpoint_precedence() {
+        let config = Config {
+            rpc_url: Some("http://config".to_owned()),
+            ..Config::default()
+        };
+        let path = PathBuf::from("config.toml");
+
+        let flag = resolve_rpc_endpoint(
+            Some("http://flag"),
+            Some("http://env".to_owned()),
+            &config,
+            path.clone(),
+        )
+        .unwrap();
+        assert_eq!(flag.url, "http://flag");
+        assert_eq!(flag.source, RpcSource::Flag);
+
+        let env = resolve_rpc_endpoint(None, Some("http://env".to_owned()), &config, path.clone())
+            .unwrap();
+        assert_eq!(env.source, RpcSource::Env);
+
+        let file = resolve_rpc_endpoint(None, None, &config, path.clone()).unwrap();
+        assert_eq!(file.source, RpcSource::ConfigFile(path.clone()));
+
+        assert!(resolve_rpc_endpoint(None, None, &Config::default(), path).is_err());
+    }
+
+    #[test]
+    fn test_is_ens_name() {
+        assert!(is_ens_name("treasury.eth"));
+        assert!(is_ens_name("vault.treasury.eth"));
+        assert!(!is_ens_name("0x2a4d5b5f1a6c0f0d8ee9e5b5a0c64f1c9b6a6f2e"));
+        assert!(!is_ens_name("treasury"));
+    }
+}
@@ -1,15 +1,63 @@
-use crate::types::DbInfo;
-use std::path::PathBuf;
+use crate::error::NumbaError;
+use crate::types::{DbInfo, MergeStats};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{fs, vec};
 
 use rustbreak::{deser::Ron, FileDatabase};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use crate::types::{DbUserVaultHoldings, UserVaultHoldings};
+use crate::types::{DbUserVaultHoldings, UserVaultHoldings, VaultIdentifier};
+use crate::utils::checksum_address;
+
+pub mod migrations;
+pub mod sqlite;
+
+use sqlite::SqliteStore;
 
 pub type Db = FileDatabase<Data, Ron>;
 
+/// Persists the snapshots of each group (holder address and vault)
+pub trait SnapshotStore {
+    /// Append an entry unless it equals the last entry of the group
+    fn save_entry(&self, group_id: &str, entry: &UserVaultHoldings) -> anyhow::Result<()>;
+
+    /// Merge entries into a group in timestamp order, see `merge_entries`
+    fn merge_entries(
+        &self,
+        group_id: &str,
+        new_entries: &[UserVaultHoldings],
+    ) -> anyhow::Result<MergeStats>;
+
+    /// All entries of a group, oldest first
+    fn read_entries(&self, group_id: &str) -> anyhow::Result<Vec<UserVaultHoldings>>;
+
+    fn db_info(&self, group_id: &str) -> anyhow::Result<DbInfo>;
+}
+
+/// The file format snapshots are stored in
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    #[default]
+    Sqlite,
+    Ron,
+}
+
+impl FromStr for StoreBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sqlite" => Ok(StoreBackend::Sqlite),
+            "ron" => Ok(StoreBackend::Ron),
+            _ => Err(format!("Unknown store '{}', expected sqlite or ron", value)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Data {
     // "0xdeadbeef_vaultname" -> entries
@@ -17,6 +65,21 @@ pub struct Data {
     pub any: HashMap<String, String>,
 }
 
+impl Data {
+    /// An empty database at the current schema version
+    pub fn new() -> Data {
+        let mut any = HashMap::new();
+        any.insert(
+            migrations::SCHEMA_VERSION_KEY.to_owned(),
+            migrations::SCHEMA_VERSION.to_string(),
+        );
+        Data {
+            group_entries: HashMap::new(),
+            any,
+        }
+    }
+}
+
 pub fn get_app_dir() -> Result<PathBuf, &'static str> {
     dirs::data_local_dir()
         .ok_or("Can't obtain system application directory")
@@ -40,15 +103,67 @@ pub fn init_default_db() -> Result<Db, &'static str> {
     init_db(app_dir)
 }
 
+/// Open the store of the given backend in the application directory. Until the entries of an
+/// existing `db.ron` have been copied into the SQLite store, every open retries the copy. The
+/// `db.ron` file itself is only read, even if it has an older schema.
+pub fn open_default_store(backend: StoreBackend) -> anyhow::Result<Box<dyn SnapshotStore>> {
+    match backend {
+        StoreBackend::Ron => Ok(Box::new(
+            init_default_db().map_err(|err| anyhow::anyhow!(err))?,
+        )),
+        StoreBackend::Sqlite => {
+            let app_dir = enforce_app_dir().map_err(|err| anyhow::anyhow!(err))?;
+            let sqlite_path = app_dir.join("db.sqlite");
+            let ron_path = app_dir.join("db.ron");
+            let store = SqliteStore::open(&sqlite_path)?;
+            if !store.ron_imported()? {
+                if ron_path.exists() {
+                    migrate_to_sqlite(&ron_path, &store)?;
+                } else {
+                    // A `db.ron` created later on belongs to `--store ron` and isn't copied
+                    store.import_ron(&HashMap::new())?;
+                }
+            }
+            Ok(Box::new(store))
+        }
+    }
+}
+
+/// Copy every entry of the RON database at `path` into a SQLite store, in a single transaction
+pub fn migrate_to_sqlite(path: &Path, store: &SqliteStore) -> anyhow::Result<()> {
+    let data = migrations::read_file(path).map_err(|err| anyhow::anyhow!(err))?;
+    store.import_ron(&data.group_entries)
+}
+
+/// The group id with the holder address in checksum encoding, so that snapshots of the same
+/// holder end up in the same group. Group ids without a hex address are returned unchanged.
+pub fn checksum_group_id(group_id: &str) -> String {
+    group_id
+        .split_once('-')
+        .and_then(|(address, vault_name)| {
+            checksum_address(address).map(|address| VaultIdentifier::new(&address, vault_name).id())
+        })
+        .unwrap_or_else(|| group_id.to_owned())
+}
+
+/// Open the database at `path`, creating it if it doesn't exist. Databases written with an
+/// older schema are migrated to the current one.
 pub fn init_db(path: PathBuf) -> Result<Db, &'static str> {
-    FileDatabase::load_from_path_or(
-        path,
-        Data {
-            group_entries: HashMap::new(),
-            any: HashMap::new(),
-        },
-    )
-    .map_err(|_| "Could not read database")
+    let migrated = if path.exists() {
+        migrations::migrate_file(&path)?
+    } else {
+        None
+    };
+    match migrated {
+        Some(data) => {
+            let db =
+                FileDatabase::create_at_path(path, data).map_err(|_| "Could not read database")?;
+            db.save().map_err(|_| "Could not save migrated database")?;
+            Ok(db)
+        }
+        None => FileDatabase::load_from_path_or(path, Data::new())
+            .map_err(|_| "Could not read database"),
+    }
 }
 
 pub fn save_entry(
@@ -76,10 +191,42 @@ pub fn save_entry(
     Ok(())
 }
 
-pub fn read_entries(db: &Db, group_id: &str) -> Vec<UserVaultHoldings> {
+/// Merge entries into a group in timestamp order. An entry is skipped if the group already
+/// has an entry with the same timestamp or if it equals the entry right before or after it, so
+/// that no two subsequent entries are equal, which is the rule `save_entry` applies.
+pub fn merge_entries(
+    db: &Db,
+    group_id: &str,
+    new_entries: &[UserVaultHoldings],
+) -> Result<MergeStats, rustbreak::RustbreakError> {
+    let mut sorted: Vec<DbUserVaultHoldings> = new_entries.iter().map(Into::into).collect();
+    sorted.sort_by_key(|entry| entry.timestamp);
+
+    let mut stats = MergeStats::default();
+    db.write(|db| {
+        let entries = db.group_entries.entry(group_id.to_owned()).or_default();
+        for db_entry in sorted {
+            let position = entries.partition_point(|entry| entry.timestamp <= db_entry.timestamp);
+            let duplicate = position > 0
+                && matches!(entries.get(position - 1), Some(previous)
+                    if previous.timestamp == db_entry.timestamp || previous == &db_entry)
+                || entries.get(position) == Some(&db_entry);
+            if duplicate {
+                stats.skipped += 1;
+            } else {
+                entries.insert(position, db_entry);
+                stats.added += 1;
+            }
+        }
+    })?;
+    db.save()?;
+    Ok(stats)
+}
+
+pub fn read_entries(db: &Db, group_id: &str) -> Result<Vec<UserVaultHoldings>, NumbaError> {
     match db.read(|db| db.group_entries.get(group_id).cloned()) {
-        Ok(Some(val)) => val.iter().map(UserVaultHoldings::from).collect(),
-        _ => vec![],
+        Ok(Some(val)) => val.iter().map(UserVaultHoldings::try_from).collect(),
+        _ => Ok(vec![]),
     }
 }
 
@@ -98,14 +245,39 @@ pub fn db_info(db: &Db, group_id: &str) -> DbInfo {
     }
 }
 
+impl SnapshotStore for Db {
+    fn save_entry(&self, group_id: &str, entry: &UserVaultHoldings) -> anyhow::Result<()> {
+        Ok(save_entry(self, group_id, entry)?)
+    }
+
+    fn merge_entries(
+        &self,
+        group_id: &str,
+        new_entries: &[UserVaultHoldings],
+    ) -> anyhow::Result<MergeStats> {
+        Ok(merge_entries(self, group_id, new_entries)?)
+    }
+
+    fn read_entries(&self, group_id: &str) -> anyhow::Result<Vec<UserVaultHoldings>> {
+        Ok(read_entries(self, group_id)?)
+    }
+
+    fn db_info(&self, group_id: &str) -> anyhow::Result<DbInfo> {
+        Ok(db_info(self, group_id))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::store::sqlite::SqliteStore;
     use crate::store::{
-        get_app_dir, init_db, read_entries, save_entry, DbUserVaultHoldings, UserVaultHoldings,
+        get_app_dir, init_db, migrate_to_sqlite, read_entries, save_entry, SnapshotStore,
+        UserVaultHoldings,
     };
-    use crate::utils::unix_time;
+    use bigdecimal::BigDecimal;
+    use std::fs;
     use std::path::PathBuf;
-    use tempfile::tempdir;
+    use tempfile::{tempdir, TempDir};
 
     #[test]
     fn test_get_app_dir() {
@@ -123,15 +295,151 @@ mod tests {
 
         let entry_1 = UserVaultHoldings::zero();
         let mut entry_2 = UserVaultHoldings::zero();
-        // Different timestamps should be ignored
-        entry_2.timestamp = entry_2.timestamp + 1;
+        // Different timestamps and block numbers should be ignored
+        entry_2.timestamp += 1;
+        entry_2.block_number += 1;
 
         let group_id = "0xdeadbeef_some_vault";
 
-        save_entry(&db, &group_id, &entry_1).unwrap();
-        save_entry(&db, &group_id, &entry_2).unwrap();
+        save_entry(&db, group_id, &entry_1).unwrap();
+        save_entry(&db, group_id, &entry_2).unwrap();
 
-        let entries = read_entries(&db, &group_id);
+        let entries = read_entries(&db, group_id).unwrap();
         assert_eq!(entries.len(), 1);
     }
+
+    #[test]
+    fn test_reads_entries_without_block_number() {
+        let db_path = tempdir().unwrap();
+        let db_path = db_path.path().join("test3.ron");
+        fs::write(
+            &db_path,
+            r#"(
+    group_entries: {
+        "0xdeadbeef_some_vault": [
+            (
+                timestamp: 1614556800,
+                price_per_share: "1.01",
+                usd_1: "10",
+                usd_2: "20",
+                usd_all: "30",
+            ),
+        ],
+    },
+    any: {},
+)"#,
+        )
+        .unwrap();
+        let db = init_db(db_path).unwrap();
+
+        let entries = read_entries(&db, "0xdeadbeef_some_vault").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].timestamp, 1614556800);
+        assert_eq!(entries[0].block_number, 0);
+    }
+
+    /// A RON and a SQLite store, to run the same checks against both
+    fn stores() -> Vec<(TempDir, Box<dyn SnapshotStore>)> {
+        let dir = tempdir().unwrap();
+        let db = init_db(dir.path().join("db.ron")).unwrap();
+        vec![
+            (dir, Box::new(db)),
+            (
+                tempdir().unwrap(),
+                Box::new(SqliteStore::open_in_memory().unwrap()),
+            ),
+        ]
+    }
+
+    fn with_value(timestamp: u64, value: u64) -> UserVaultHoldings {
+        UserVaultHoldings::with_timestamp_and_value(timestamp, value)
+    }
+
+    fn check_save_skips_duplicate_subsequent_entry(store: &dyn SnapshotStore) {
+        let group_id = "0xdeadbeef_some_vault";
+
+        store.save_entry(group_id, &with_value(100, 1)).unwrap();
+        store.save_entry(group_id, &with_value(200, 1)).unwrap();
+        store.save_entry(group_id, &with_value(300, 2)).unwrap();
+
+        let info = store.db_info(group_id).unwrap();
+        assert_eq!(info.entry_count, 2);
+        assert_eq!(info.oldest_timestamp, 100);
+        assert_eq!(info.newest_timestamp, 300);
+    }
+
+    fn check_merge_entries_in_timestamp_order(store: &dyn SnapshotStore) {
+        let group_id = "0xdeadbeef_some_vault";
+
+        store.save_entry(group_id, &with_value(100, 1)).unwrap();
+        store.save_entry(group_id, &with_value(300, 3)).unwrap();
+
+        let stats = store
+            .merge_entries(
+                group_id,
+                &[
+                    with_value(400, 4),
+                    with_value(200, 2),
+                    // Same timestamp as an existing entry
+                    with_value(300, 5),
+                    // Equal to the entry right before it
+                    with_value(250, 2),
+                    // Equal to the entry right after it
+                    with_value(280, 3),
+                ],
+            )
+            .unwrap();
+        assert_eq!(stats.added, 2);
+        assert_eq!(stats.skipped, 3);
+
+        let timestamps: Vec<u64> = store
+            .read_entries(group_id)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![100, 200, 300, 400]);
+    }
+
+    #[test]
+    fn test_save_skips_duplicate_subsequent_entry() {
+        for (_dir, store) in stores() {
+            check_save_skips_duplicate_subsequent_entry(store.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_merge_entries_in_timestamp_order() {
+        for (_dir, store) in stores() {
+            check_merge_entries_in_timestamp_order(store.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_migrate_to_sqlite_leaves_ron_file_untouched() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test5.ron");
+        // An unversioned file, which opening it as a RON store would migrate
+        let content = r#"(
+    group_entries: {
+        "0xdeadbeef_some_vault": [
+            (timestamp: 100, price_per_share: "1", usd_1: "0", usd_2: "0", usd_all: "0"),
+            (timestamp: 200, price_per_share: "1", usd_1: "1", usd_2: "1", usd_all: "2"),
+        ],
+    },
+    any: {},
+)"#;
+        fs::write(&db_path, content).unwrap();
+
+        let store = SqliteStore::open_in_memory().unwrap();
+        assert!(!store.ron_imported().unwrap());
+        migrate_to_sqlite(&db_path, &store).unwrap();
+        assert!(store.ron_imported().unwrap());
+
+        let entries = store.read_entries("0xdeadbeef_some_vault").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].usd_all, BigDecimal::from(2));
+        assert_eq!(fs::read_to_string(&db_path).unwrap(), content);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
 }
@@ -0,0 +1,337 @@
+use ron::value::{Map, Number, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::store::{checksum_group_id, Data};
+use crate::types::DbCoinHolding;
+
+/// The key in `Data::any` that holds the schema version of the database
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+type Migration = fn(&mut Value) -> Result<(), &'static str>;
+
+/// Migration `i` takes a database from schema version `i` to `i + 1`. Databases without a
+/// version predate versioning and have version 0.
+const MIGRATIONS: [Migration; 4] = [
+    add_block_number,
+    add_shares,
+    checksum_group_ids,
+    split_coins,
+];
+
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// Bring the database file at `path` up to `SCHEMA_VERSION`. The original file is copied to
+/// `<path>.v<version>.bak` first. Returns the migrated data, or `None` if the file is already
+/// up to date.
+pub fn migrate_file(path: &Path) -> Result<Option<Data>, &'static str> {
+    let content = fs::read_to_string(path).map_err(|_| "Could not read database")?;
+    match migrate(&content)? {
+        Some((version, data)) => {
+            fs::copy(path, backup_path(path, version)).map_err(|_| "Could not back up database")?;
+            Ok(Some(data))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Read the database file at `path` at `SCHEMA_VERSION` without writing to it. Older schemas are
+/// only migrated in memory.
+pub fn read_file(path: &Path) -> Result<Data, &'static str> {
+    let content = fs::read_to_string(path).map_err(|_| "Could not read database")?;
+    match migrate(&content)? {
+        Some((_, data)) => Ok(data),
+        None => ron::from_str(&content).map_err(|_| "Could not parse database"),
+    }
+}
+
+/// Migrate the content of a database file. Returns the original schema version together with
+/// the migrated data, or `None` if the content is already up to date.
+fn migrate(content: &str) -> Result<Option<(usize, Data)>, &'static str> {
+    let mut value: Value = ron::from_str(content).map_err(|_| "Could not parse database")?;
+
+    let version = schema_version(&value)?;
+    if version > SCHEMA_VERSION {
+        return Err("Database was written by a newer version of numbagoup");
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    for migration in &MIGRATIONS[version..] {
+        migration(&mut value)?;
+    }
+
+    let mut data: Data = value
+        .into_rust()
+        .map_err(|_| "Could not read migrated database")?;
+    data.any
+        .insert(SCHEMA_VERSION_KEY.to_owned(), SCHEMA_VERSION.to_string());
+    Ok(Some((version, data)))
+}
+
+fn backup_path(path: &Path, version: usize) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(format!(".v{}.bak", version));
+    path.with_file_name(file_name)
+}
+
+fn schema_version(data: &Value) -> Result<usize, &'static str> {
+    let any = match data {
+        Value::Map(data) => field(data, "any"),
+        _ => None,
+    };
+    match any {
+        Some(Value::Map(any)) => match field(any, SCHEMA_VERSION_KEY) {
+            Some(Value::String(version)) => version
+                .parse()
+                .map_err(|_| "Invalid schema version in database"),
+            Some(_) => Err("Invalid schema version in database"),
+            None => Ok(0),
+        },
+        _ => Ok(0),
+    }
+}
+
+fn field<'a>(map: &'a Map, name: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(key, _)| matches!(key, Value::String(key) if key == name))
+        .map(|(_, value)| value)
+}
+
+fn group_entries(data: &mut Value) -> Result<Option<&mut Map>, &'static str> {
+    let data = match data {
+        Value::Map(data) => data,
+        _ => return Err("Unexpected database layout"),
+    };
+    let groups = data
+        .iter_mut()
+        .find(|(key, _)| matches!(key, Value::String(key) if key == "group_entries"))
+        .map(|(_, groups)| groups);
+    match groups {
+        Some(Value::Map(groups)) => Ok(Some(groups)),
+        None => Ok(None),
+        _ => Err("Unexpected database layout"),
+    }
+}
+
+/// Call `migrate` with the group id on every entry of every group
+fn for_each_entry(
+    data: &mut Value,
+    mut migrate: impl FnMut(&str, &mut Map),
+) -> Result<(), &'static str> {
+    let groups = match group_entries(data)? {
+        Some(groups) => groups,
+        None => return Ok(()),
+    };
+    for (group_id, entries) in groups.iter_mut() {
+        let group_id = match group_id {
+            Value::String(group_id) => group_id,
+            _ => return Err("Unexpected database layout"),
+        };
+        let entries = match entries {
+            Value::Seq(entries) => entries,
+            _ => return Err("Unexpected database layout"),
+        };
+        for entry in entries {
+            match entry {
+                Value::Map(entry) => migrate(group_id, entry),
+                _ => return Err("Unexpected database layout"),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_default(entry: &mut Map, name: &str, value: Value) {
+    if field(entry, name).is_none() {
+        entry.insert(Value::String(name.to_owned()), value);
+    }
+}
+
+/// Version 1 records the block number of each snapshot, unknown for older entries
+fn add_block_number(data: &mut Value) -> Result<(), &'static str> {
+    for_each_entry(data, |_, entry| {
+        set_default(entry, "block_number", Value::Number(Number::from(0u64)))
+    })
+}
+
+/// Version 2 records the share balance of each snapshot, unknown for older entries
+fn add_shares(data: &mut Value) -> Result<(), &'static str> {
+    for_each_entry(data, |_, entry| {
+        set_default(entry, "shares", Value::Option(None))
+    })
+}
+
+/// Version 3 keys groups by the checksum address of the holder. Groups that only differed in the
+/// case of the address are merged.
+fn checksum_group_ids(data: &mut Value) -> Result<(), &'static str> {
+    let groups = match group_entries(data)? {
+        Some(groups) => groups,
+        None => return Ok(()),
+    };
+    let mut migrated = Map::new();
+    for (group_id, entries) in groups.iter() {
+        let group_id = match group_id {
+            Value::String(group_id) => Value::String(checksum_group_id(group_id)),
+            _ => return Err("Unexpected database layout"),
+        };
+        let entries = match entries {
+            Value::Seq(entries) => entries.clone(),
+            _ => return Err("Unexpected database layout"),
+        };
+        let merged = match migrated.remove(&group_id) {
+            Some(Value::Seq(mut merged)) => {
+                merged.extend(entries);
+                merged.sort_by_key(timestamp);
+                merged
+            }
+            _ => entries,
+        };
+        migrated.insert(group_id, Value::Seq(merged));
+    }
+    *groups = migrated;
+    Ok(())
+}
+
+/// Version 4 replaces the two coin values of each snapshot by a list of coins
+fn split_coins(data: &mut Value) -> Result<(), &'static str> {
+    for_each_entry(data, |group_id, entry| {
+        let mut take = |name: &str| match entry.remove(&Value::String(name.to_owned())) {
+            Some(Value::String(value)) => value,
+            _ => "0".to_owned(),
+        };
+        let (usd_1, usd_2) = (take("usd_1"), take("usd_2"));
+        let coins = legacy_coins(vault_name(group_id), &usd_1, &usd_2)
+            .into_iter()
+            .map(|coin| {
+                Value::Map(
+                    vec![
+                        ("symbol", coin.symbol),
+                        ("amount", coin.amount),
+                        ("usd", coin.usd),
+                    ]
+                    .into_iter()
+                    .map(|(key, value)| (Value::String(key.to_owned()), Value::String(value)))
+                    .collect(),
+                )
+            })
+            .collect();
+        set_default(entry, "coins", Value::Seq(coins));
+    })
+}
+
+/// The vault name part of a group id
+pub fn vault_name(group_id: &str) -> &str {
+    group_id
+        .split_once('-')
+        .map_or(group_id, |(_, vault_name)| vault_name)
+}
+
+/// The coins of a snapshot that only recorded the USD values of two coins. The amounts weren't
+/// recorded, so the values stand in for them, which they equal as long as coins count as 1 USD.
+pub fn legacy_coins(vault_name: &str, usd_1: &str, usd_2: &str) -> Vec<DbCoinHolding> {
+    let symbols = match vault_name {
+        "crvCOMP" => ["DAI", "USDC"],
+        "crvSAAVE" => ["aDAI", "aSUSD"],
+        _ => ["coin 1", "coin 2"],
+    };
+    symbols
+        .iter()
+        .zip(&[usd_1, usd_2])
+        .map(|(symbol, value)| DbCoinHolding {
+            symbol: (*symbol).to_owned(),
+            amount: (*value).to_owned(),
+            usd: (*value).to_owned(),
+        })
+        .collect()
+}
+
+fn timestamp(entry: &Value) -> Option<i64> {
+    match entry {
+        Value::Map(entry) => match field(entry, "timestamp") {
+            Some(Value::Number(timestamp)) => timestamp.as_i64(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::migrations::{migrate_file, SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+    use crate::utils::checksum_address;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_migrate_unversioned_file() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db.ron");
+        let content = r#"(
+    group_entries: {
+        "0xdeadbeef_some_vault": [
+            (
+                timestamp: 1614556800,
+                price_per_share: "1.01",
+                usd_1: "10",
+                usd_2: "20",
+                usd_all: "30",
+            ),
+        ],
+    },
+    any: {},
+)"#;
+        fs::write(&db_path, content).unwrap();
+
+        let data = migrate_file(&db_path).unwrap().unwrap();
+        let entries = &data.group_entries["0xdeadbeef_some_vault"];
+        assert_eq!(entries[0].block_number, 0);
+        assert_eq!(entries[0].shares, None);
+        assert_eq!(
+            data.any.get(SCHEMA_VERSION_KEY),
+            Some(&SCHEMA_VERSION.to_string())
+        );
+
+        let backup = fs::read_to_string(dir.path().join("db.ron.v0.bak")).unwrap();
+        assert_eq!(backup, content);
+    }
+
+    #[test]
+    fn test_migrate_merges_groups_by_checksum_address() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db.ron");
+        let address = "0x2a4d5b5f1a6c0f0d8ee9e5b5a0c64f1c9b6a6f2e";
+        let checksummed = checksum_address(address).unwrap();
+        let entry = |timestamp: u64| {
+            format!(
+                r#"(timestamp: {}, block_number: 0, price_per_share: "1", shares: None,
+                    usd_1: "0", usd_2: "0", usd_all: "0")"#,
+                timestamp
+            )
+        };
+        let content = format!(
+            r#"(
+    group_entries: {{
+        "{}-crvCOMP": [{}, {}],
+        "{}-crvCOMP": [{}],
+    }},
+    any: {{ "schema_version": "2" }},
+)"#,
+            address,
+            entry(100),
+            entry(300),
+            checksummed,
+            entry(200)
+        );
+        fs::write(&db_path, content).unwrap();
+
+        let data = migrate_file(&db_path).unwrap().unwrap();
+        assert_eq!(data.group_entries.len(), 1);
+        let timestamps: Vec<u64> = data.group_entries[&format!("{}-crvCOMP", checksummed)]
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![100, 200, 300]);
+    }
+}
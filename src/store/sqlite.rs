@@ -0,0 +1,368 @@
+use anyhow::Result;
+use rusqlite::types::Type;
+use rusqlite::Error::FromSqlConversionFailure;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+
+use crate::store::migrations::{legacy_coins, vault_name};
+use crate::store::{checksum_group_id, SnapshotStore};
+use crate::types::{DbInfo, DbUserVaultHoldings, MergeStats, UserVaultHoldings};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS entries (
+    id INTEGER PRIMARY KEY,
+    group_id TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    price_per_share TEXT NOT NULL,
+    shares TEXT,
+    -- JSON list of the coins of the snapshot
+    coins TEXT NOT NULL,
+    usd_all TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS entries_group_id_timestamp ON entries (group_id, timestamp);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// The key in the meta table that is set once the entries of `db.ron` have been copied over
+const RON_IMPORTED_KEY: &str = "ron_imported";
+
+/// Stored in `PRAGMA user_version`. Version 1 keys groups by the checksum address of the holder,
+/// version 2 replaces the usd_1 and usd_2 columns by the coins column, version 3 adds the meta
+/// table.
+const SCHEMA_VERSION: i64 = 3;
+
+const ENTRY_COLUMNS: &str = "timestamp, block_number, price_per_share, shares, coins, usd_all";
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<SqliteStore> {
+        SqliteStore::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<SqliteStore> {
+        SqliteStore::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<SqliteStore> {
+        let version: i64 = conn.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+        let has_entries: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'entries'",
+            params![],
+            |row| row.get(0),
+        )?;
+        if has_entries && version < 1 {
+            checksum_group_ids(&conn)?;
+        }
+        if has_entries && version < 2 {
+            split_coins(&conn)?;
+        }
+        conn.execute_batch(SCHEMA)?;
+        if has_entries && version < 3 {
+            // Stores written before the meta table only exist with entries if the import of
+            // `db.ron` went through
+            conn.execute(
+                "INSERT OR IGNORE INTO meta (key, value)
+                 SELECT ?1, '1' WHERE EXISTS (SELECT 1 FROM entries)",
+                params![RON_IMPORTED_KEY],
+            )?;
+        }
+        conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+        Ok(SqliteStore { conn })
+    }
+
+    /// Whether the entries of `db.ron` have been copied into the store
+    pub fn ron_imported(&self) -> Result<bool> {
+        let imported = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM meta WHERE key = ?1",
+                params![RON_IMPORTED_KEY],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(imported.is_some())
+    }
+
+    /// Insert the entries of `db.ron` as they are, without applying the dedupe rule, and mark
+    /// the import as done. Either all entries are copied or none.
+    pub fn import_ron<'a>(
+        &self,
+        group_entries: impl IntoIterator<Item = (&'a String, &'a Vec<DbUserVaultHoldings>)>,
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for (group_id, entries) in group_entries {
+            for entry in entries {
+                insert(&tx, group_id, entry)?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, '1')",
+            params![RON_IMPORTED_KEY],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The last entry of the group at or before `timestamp`
+    fn entry_at_or_before(
+        &self,
+        group_id: &str,
+        timestamp: u64,
+    ) -> Result<Option<DbUserVaultHoldings>> {
+        let entry = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM entries WHERE group_id = ?1 AND timestamp <= ?2
+                     ORDER BY timestamp DESC, id DESC LIMIT 1",
+                    ENTRY_COLUMNS
+                ),
+                params![group_id, to_sql_int(timestamp)?],
+                from_row,
+            )
+            .optional()?;
+        Ok(entry)
+    }
+
+    /// The first entry of the group after `timestamp`
+    fn entry_after(&self, group_id: &str, timestamp: u64) -> Result<Option<DbUserVaultHoldings>> {
+        let entry = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM entries WHERE group_id = ?1 AND timestamp > ?2
+                     ORDER BY timestamp, id LIMIT 1",
+                    ENTRY_COLUMNS
+                ),
+                params![group_id, to_sql_int(timestamp)?],
+                from_row,
+            )
+            .optional()?;
+        Ok(entry)
+    }
+
+    fn last_entry(&self, group_id: &str) -> Result<Option<DbUserVaultHoldings>> {
+        self.entry_at_or_before(group_id, i64::MAX as u64)
+    }
+}
+
+impl SnapshotStore for SqliteStore {
+    fn save_entry(&self, group_id: &str, entry: &UserVaultHoldings) -> Result<()> {
+        let db_entry: DbUserVaultHoldings = entry.into();
+        if !matches!(self.last_entry(group_id)?, Some(previous) if previous == db_entry) {
+            insert(&self.conn, group_id, &db_entry)?;
+        }
+        Ok(())
+    }
+
+    fn merge_entries(
+        &self,
+        group_id: &str,
+        new_entries: &[UserVaultHoldings],
+    ) -> Result<MergeStats> {
+        let mut sorted: Vec<DbUserVaultHoldings> = new_entries.iter().map(Into::into).collect();
+        sorted.sort_by_key(|entry| entry.timestamp);
+
+        let mut stats = MergeStats::default();
+        let tx = self.conn.unchecked_transaction()?;
+        for db_entry in sorted {
+            let duplicate = matches!(self.entry_at_or_before(group_id, db_entry.timestamp)?,
+                Some(previous) if previous.timestamp == db_entry.timestamp || previous == db_entry)
+                || self.entry_after(group_id, db_entry.timestamp)?.as_ref() == Some(&db_entry);
+            if duplicate {
+                stats.skipped += 1;
+            } else {
+                insert(&tx, group_id, &db_entry)?;
+                stats.added += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(stats)
+    }
+
+    fn read_entries(&self, group_id: &str) -> Result<Vec<UserVaultHoldings>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM entries WHERE group_id = ?1 ORDER BY timestamp, id",
+            ENTRY_COLUMNS
+        ))?;
+        let entries = statement
+            .query_map(params![group_id], from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries
+            .iter()
+            .map(UserVaultHoldings::try_from)
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn db_info(&self, group_id: &str) -> Result<DbInfo> {
+        let (entry_count, oldest_timestamp, newest_timestamp) = self.conn.query_row(
+            "SELECT COUNT(*), MIN(timestamp), MAX(timestamp) FROM entries WHERE group_id = ?1",
+            params![group_id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<i64>>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                ))
+            },
+        )?;
+        Ok(DbInfo {
+            entry_count: entry_count.try_into()?,
+            oldest_timestamp: oldest_timestamp.unwrap_or_default().try_into()?,
+            newest_timestamp: newest_timestamp.unwrap_or_default().try_into()?,
+        })
+    }
+}
+
+/// Rewrite the group ids to use the checksum address of the holder
+fn checksum_group_ids(conn: &Connection) -> Result<()> {
+    let group_ids = conn
+        .prepare("SELECT DISTINCT group_id FROM entries")?
+        .query_map(params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let tx = conn.unchecked_transaction()?;
+    for group_id in group_ids {
+        let checksummed = checksum_group_id(&group_id);
+        if checksummed != group_id {
+            tx.execute(
+                "UPDATE entries SET group_id = ?1 WHERE group_id = ?2",
+                params![checksummed, group_id],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Move the usd_1 and usd_2 columns into the coins column. SQLite can't drop columns, so the
+/// entries are copied into a new table.
+fn split_coins(conn: &Connection) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(
+        "ALTER TABLE entries RENAME TO entries_v1;
+         DROP INDEX entries_group_id_timestamp;",
+    )?;
+    tx.execute_batch(SCHEMA)?;
+    let entries = tx
+        .prepare(
+            "SELECT group_id, timestamp, block_number, price_per_share, shares, usd_1, usd_2,
+                    usd_all
+             FROM entries_v1 ORDER BY id",
+        )?
+        .query_map(params![], |row| {
+            let group_id: String = row.get(0)?;
+            let usd_1: String = row.get(5)?;
+            let usd_2: String = row.get(6)?;
+            let entry = DbUserVaultHoldings {
+                timestamp: row.get::<_, i64>(1)? as u64,
+                block_number: row.get::<_, i64>(2)? as u64,
+                price_per_share: row.get(3)?,
+                shares: row.get(4)?,
+                coins: legacy_coins(vault_name(&group_id), &usd_1, &usd_2),
+                usd_all: row.get(7)?,
+            };
+            Ok((group_id, entry))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (group_id, entry) in entries {
+        insert(&tx, &group_id, &entry)?;
+    }
+    tx.execute_batch("DROP TABLE entries_v1")?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn to_sql_int(value: u64) -> Result<i64> {
+    Ok(value.try_into()?)
+}
+
+fn insert(conn: &Connection, group_id: &str, entry: &DbUserVaultHoldings) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO entries (group_id, {}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            ENTRY_COLUMNS
+        ),
+        params![
+            group_id,
+            to_sql_int(entry.timestamp)?,
+            to_sql_int(entry.block_number)?,
+            entry.price_per_share,
+            entry.shares,
+            serde_json::to_string(&entry.coins)?,
+            entry.usd_all,
+        ],
+    )?;
+    Ok(())
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<DbUserVaultHoldings> {
+    Ok(DbUserVaultHoldings {
+        timestamp: row.get::<_, i64>(0)? as u64,
+        block_number: row.get::<_, i64>(1)? as u64,
+        price_per_share: row.get(2)?,
+        shares: row.get(3)?,
+        coins: serde_json::from_str(&row.get::<_, String>(4)?)
+            .map_err(|err| FromSqlConversionFailure(4, Type::Text, Box::new(err)))?,
+        usd_all: row.get(5)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::sqlite::SqliteStore;
+    use crate::store::SnapshotStore;
+    use bigdecimal::BigDecimal;
+    use rusqlite::{params, Connection};
+
+    #[test]
+    fn test_init_splits_legacy_coin_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE entries (
+                id INTEGER PRIMARY KEY,
+                group_id TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                block_number INTEGER NOT NULL,
+                price_per_share TEXT NOT NULL,
+                shares TEXT,
+                usd_1 TEXT NOT NULL,
+                usd_2 TEXT NOT NULL,
+                usd_all TEXT NOT NULL
+            );
+            CREATE INDEX entries_group_id_timestamp ON entries (group_id, timestamp);
+            PRAGMA user_version = 1;",
+        )
+        .unwrap();
+        let group_id = "0xdeadbeef-crvSAAVE";
+        conn.execute(
+            "INSERT INTO entries (group_id, timestamp, block_number, price_per_share, shares,
+                                  usd_1, usd_2, usd_all)
+             VALUES (?1, 100, 0, '1.01', NULL, '10', '20', '30')",
+            params![group_id],
+        )
+        .unwrap();
+
+        let store = SqliteStore::init(conn).unwrap();
+        // The entries of a store from before the meta table were copied from db.ron
+        assert!(store.ron_imported().unwrap());
+        let entries = store.read_entries(group_id).unwrap();
+        assert_eq!(entries.len(), 1);
+        let symbols: Vec<&str> = entries[0]
+            .coins
+            .iter()
+            .map(|coin| coin.symbol.as_str())
+            .collect();
+        assert_eq!(symbols, vec!["aDAI", "aSUSD"]);
+        assert_eq!(entries[0].coins[1].usd, BigDecimal::from(20));
+        assert_eq!(entries[0].usd_all, BigDecimal::from(30));
+    }
+}
@@ -1,7 +1,11 @@
+use std::convert::TryFrom;
 use std::str::FromStr;
 
+use crate::constants::{DAY_IN_SEC, HOUR_IN_SEC, MONTH_IN_SEC, WEEK_IN_SEC};
+use crate::error::NumbaError;
 use crate::utils::unix_time;
 use bigdecimal::BigDecimal;
+use ethers::types::Address;
 use serde::{Deserialize, Serialize};
 
 pub struct VaultIdentifier {
@@ -22,6 +26,22 @@ impl VaultIdentifier {
     }
 }
 
+/// A yearn vault in which a holder owns shares
+#[derive(Debug, Clone)]
+pub struct VaultPosition {
+    pub vault_address: Address,
+    pub symbol: String,
+    pub shares: BigDecimal,
+    pub price_per_share: BigDecimal,
+}
+
+/// The block all reads of a snapshot are pinned to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotBlock {
+    pub number: u64,
+    pub timestamp: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct DbInfo {
     pub oldest_timestamp: u64,
@@ -29,36 +49,156 @@ pub struct DbInfo {
     pub entry_count: usize,
 }
 
-#[derive(Debug, Clone)]
+/// A deposit (positive amount) or withdrawal (negative amount) in USD
+#[derive(Debug, Clone, PartialEq)]
+pub struct CashFlow {
+    pub timestamp: u64,
+    pub amount: BigDecimal,
+}
+
+/// How the yield of a vault is calculated
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum ReturnMethod {
+    /// Chained returns of the sub periods between cash flows
+    #[default]
+    #[serde(rename = "twr")]
+    TimeWeighted,
+    /// Annualized internal rate of return (XIRR) of the cash flows
+    #[serde(rename = "mwr")]
+    MoneyWeighted,
+}
+
+impl FromStr for ReturnMethod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "twr" => Ok(ReturnMethod::TimeWeighted),
+            "mwr" => Ok(ReturnMethod::MoneyWeighted),
+            _ => Err(format!(
+                "Unknown return method '{}', expected twr or mwr",
+                value
+            )),
+        }
+    }
+}
+
+/// How the performance report is printed
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "Unknown output format '{}', expected table or json",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeStats {
+    pub added: usize,
+    pub skipped: usize,
+}
+
+/// A trailing period over which the gain, APR and APY are reported
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    /// Shown as "past <label>" in the table header
+    pub label: String,
+    pub duration_sec: u64,
+}
+
+impl Window {
+    pub fn new(label: &str, duration_sec: u64) -> Window {
+        Window {
+            label: label.to_owned(),
+            duration_sec,
+        }
+    }
+
+    /// The past hour, day, week and month
+    pub fn defaults() -> Vec<Window> {
+        vec![
+            Window::new("hour", HOUR_IN_SEC),
+            Window::new("day", DAY_IN_SEC),
+            Window::new("week", WEEK_IN_SEC),
+            Window::new("month", MONTH_IN_SEC),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowPerformance {
+    /// The label of the window
+    pub window: String,
+    pub start_value: BigDecimal,
+    pub gain: BigDecimal,
+    /// `None` if there is no rate of return, e.g. because the money-weighted return has no root
+    pub apr: Option<BigDecimal>,
+    /// `None` if there is no rate of return or the compounded rate is too large to compute
+    pub apy: Option<BigDecimal>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct VaultPerformance {
     pub gain_last_check: BigDecimal,
-    pub gain_past_hour: BigDecimal,
-    pub apy_past_hour: BigDecimal,
-    pub gain_past_day: BigDecimal,
-    pub apy_past_day: BigDecimal,
-    pub gain_past_week: BigDecimal,
-    pub apy_past_week: BigDecimal,
-    pub gain_past_month: BigDecimal,
-    pub apy_past_month: BigDecimal,
+    /// One entry per window, in the order the windows are configured
+    pub windows: Vec<WindowPerformance>,
 }
 
-#[derive(Debug, Clone)]
+/// The part of a coin of the Curve pool that the vault shares of a holder amount to
+#[derive(Debug, Clone, Serialize)]
+pub struct CoinHolding {
+    pub symbol: String,
+    /// The number of whole coins
+    pub amount: BigDecimal,
+    pub usd: BigDecimal,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct UserVaultHoldings {
     pub timestamp: u64,
+    pub block_number: u64,
     pub price_per_share: BigDecimal,
-    pub usd_1: BigDecimal,
-    pub usd_2: BigDecimal,
+    /// The number of vault shares held, unknown for snapshots taken before it was recorded
+    pub shares: Option<BigDecimal>,
+    /// One entry per coin, in the order of the coins of the pool
+    pub coins: Vec<CoinHolding>,
     pub usd_all: BigDecimal,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Hash)]
+pub struct DbCoinHolding {
+    pub symbol: String,
+    pub amount: String,
+    pub usd: String,
+}
+
 #[derive(Debug, Derivative, Serialize, Deserialize, Clone)]
 #[derivative(PartialEq, Hash)]
 pub struct DbUserVaultHoldings {
     #[derivative(PartialEq = "ignore")]
     pub timestamp: u64,
+    #[derivative(PartialEq = "ignore")]
+    #[serde(default)]
+    pub block_number: u64,
     pub price_per_share: String,
-    pub usd_1: String,
-    pub usd_2: String,
+    #[serde(default)]
+    pub shares: Option<String>,
+    pub coins: Vec<DbCoinHolding>,
     pub usd_all: String,
 }
 
@@ -67,9 +207,10 @@ impl UserVaultHoldings {
     pub fn zero() -> UserVaultHoldings {
         UserVaultHoldings {
             timestamp: unix_time(),
+            block_number: 0,
             price_per_share: BigDecimal::from(0),
-            usd_1: BigDecimal::from(0),
-            usd_2: BigDecimal::from(0),
+            shares: None,
+            coins: vec![],
             usd_all: BigDecimal::from(0),
         }
     }
@@ -79,22 +220,56 @@ impl From<&UserVaultHoldings> for DbUserVaultHoldings {
     fn from(val: &UserVaultHoldings) -> Self {
         DbUserVaultHoldings {
             timestamp: val.timestamp,
+            block_number: val.block_number,
             price_per_share: val.price_per_share.to_string(),
-            usd_1: val.usd_1.to_string(),
-            usd_2: val.usd_2.to_string(),
+            shares: val.shares.as_ref().map(ToString::to_string),
+            coins: val
+                .coins
+                .iter()
+                .map(|coin| DbCoinHolding {
+                    symbol: coin.symbol.clone(),
+                    amount: coin.amount.to_string(),
+                    usd: coin.usd.to_string(),
+                })
+                .collect(),
             usd_all: val.usd_all.to_string(),
         }
     }
 }
 
-impl From<&DbUserVaultHoldings> for UserVaultHoldings {
-    fn from(val: &DbUserVaultHoldings) -> Self {
-        UserVaultHoldings {
+impl TryFrom<&DbUserVaultHoldings> for UserVaultHoldings {
+    type Error = NumbaError;
+
+    fn try_from(val: &DbUserVaultHoldings) -> Result<Self, Self::Error> {
+        let decimal = |name: &str, value: &str| {
+            BigDecimal::from_str(value).map_err(|_| {
+                NumbaError::CorruptRow(format!(
+                    "{} of the entry at {} is not a decimal: '{}'",
+                    name, val.timestamp, value
+                ))
+            })
+        };
+        Ok(UserVaultHoldings {
             timestamp: val.timestamp,
-            price_per_share: BigDecimal::from_str(&val.price_per_share).unwrap(),
-            usd_1: BigDecimal::from_str(&val.usd_1).unwrap(),
-            usd_2: BigDecimal::from_str(&val.usd_2).unwrap(),
-            usd_all: BigDecimal::from_str(&val.usd_all).unwrap(),
-        }
+            block_number: val.block_number,
+            price_per_share: decimal("price_per_share", &val.price_per_share)?,
+            shares: val
+                .shares
+                .as_ref()
+                .map(|shares| decimal("shares", shares))
+                .transpose()?,
+            coins: val
+                .coins
+                .iter()
+                .map(|coin| {
+                    Ok(CoinHolding {
+                        symbol: coin.symbol.clone(),
+                        amount: decimal(&format!("{} amount", coin.symbol), &coin.amount)?,
+                        usd: decimal(&format!("{} USD value", coin.symbol), &coin.usd)?,
+                    })
+                })
+                .collect::<Result<_, NumbaError>>()?,
+            usd_all: decimal("usd_all", &val.usd_all)?,
+        })
     }
 }
@@ -1,5 +1,10 @@
-use bigdecimal::{BigDecimal, FromPrimitive};
-use ethers::types::U256;
+use crate::constants;
+use crate::error::NumbaError;
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use ethers::types::{Address, U256};
+use ethers::utils::to_checksum;
+use std::convert::TryInto;
 use std::str::FromStr;
 use std::time::SystemTime;
 
@@ -13,20 +18,23 @@ impl ToBigDecimal for U256 {
     }
 }
 
-pub trait Scale {
-    // https://www.reddit.com/r/Compound/comments/ezk9i4/trying_to_make_sense_of_the_exchange_rate/
-    fn scale_1e8(&self) -> BigDecimal;
+/// The checksum encoding of a hex address, or `None` if `value` isn't one
+pub fn checksum_address(value: &str) -> Option<String> {
+    value
+        .parse::<Address>()
+        .ok()
+        .map(|address| to_checksum(&address, None))
+}
 
-    fn scale_1e18(&self) -> BigDecimal;
+pub trait Scale {
+    /// Turn an amount in the smallest unit of a token with `decimals` decimals into whole tokens
+    fn scale_by_decimals(&self, decimals: u8) -> BigDecimal;
 }
 
 impl Scale for BigDecimal {
-    fn scale_1e8(&self) -> BigDecimal {
-        self / BigDecimal::from_f64(1e8).unwrap()
-    }
-
-    fn scale_1e18(&self) -> BigDecimal {
-        self / BigDecimal::from_f64(1e18).unwrap()
+    fn scale_by_decimals(&self, decimals: u8) -> BigDecimal {
+        let (digits, scale) = self.as_bigint_and_exponent();
+        BigDecimal::new(digits, scale + i64::from(decimals)).normalized()
     }
 }
 
@@ -40,6 +48,22 @@ pub fn scale_to_share(
     assets / total_shares * my_shares
 }
 
+/// Convert a number returned by a node or contract, which must fit into a u64
+pub fn u256_to_u64(value: U256, what: &str) -> Result<u64, NumbaError> {
+    if value > U256::from(u64::MAX) {
+        return Err(NumbaError::UnexpectedResponse(format!(
+            "{} {} is out of range",
+            what, value
+        )));
+    }
+    Ok(value.as_u64())
+}
+
+/// The date and time of a unix timestamp, `None` if chrono can't represent it
+pub fn date_time(timestamp: u64) -> Option<NaiveDateTime> {
+    NaiveDateTime::from_timestamp_opt(timestamp.try_into().ok()?, 0)
+}
+
 /// Return seconds since UNIX epoch
 pub fn unix_time() -> u64 {
     match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
@@ -47,3 +71,77 @@ pub fn unix_time() -> u64 {
         Err(_) => panic!("SystemTime before UNIX EPOCH!"),
     }
 }
+
+/// Parse a duration such as `30m`, `6h`, `1d` or `2w` into seconds
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}'", value))?;
+    let unit_in_sec = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => constants::HOUR_IN_SEC,
+        "d" => constants::DAY_IN_SEC,
+        "w" => constants::WEEK_IN_SEC,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit in '{}', expected one of s, m, h, d, w",
+                value
+            ))
+        }
+    };
+    if amount == 0 {
+        return Err(format!("Duration '{}' must be greater than zero", value));
+    }
+    amount
+        .checked_mul(unit_in_sec)
+        .ok_or_else(|| format!("Duration '{}' is too long", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{DAY_IN_SEC, HOUR_IN_SEC, WEEK_IN_SEC};
+    use crate::utils::{parse_duration, u256_to_u64, Scale};
+    use bigdecimal::BigDecimal;
+    use ethers::types::U256;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("6h"), Ok(6 * HOUR_IN_SEC));
+        assert_eq!(parse_duration("1d"), Ok(DAY_IN_SEC));
+        assert_eq!(parse_duration("2w"), Ok(2 * WEEK_IN_SEC));
+        assert!(parse_duration("0d").is_err());
+        assert!(parse_duration("1y").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_u256_to_u64() {
+        assert_eq!(
+            u256_to_u64(U256::from(u64::MAX), "value").unwrap(),
+            u64::MAX
+        );
+        assert!(u256_to_u64(U256::from(u64::MAX) + 1, "value").is_err());
+    }
+
+    #[test]
+    fn test_scale_by_decimals() {
+        let usdc = BigDecimal::from(1_234_560_000u64).scale_by_decimals(6);
+        assert_eq!(usdc, BigDecimal::from_str("1234.56").unwrap());
+        assert_eq!(usdc.to_string(), "1234.56");
+
+        let dai = BigDecimal::from_str("1500000000000000000").unwrap();
+        assert_eq!(
+            dai.scale_by_decimals(18),
+            BigDecimal::from_str("1.5").unwrap()
+        );
+    }
+}
@@ -1,114 +1,438 @@
 use anyhow::Result;
-use ethers::prelude::*;
+use bigdecimal::BigDecimal;
+use ethers::{abi::Uint, contract::builders::ContractCall, prelude::*};
+use futures::future::try_join_all;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
 
 use crate::constants::*;
-use crate::contracts::{CurvePoolLpToken, CurveRegistry, YearnVaultV2};
-use crate::types::UserVaultHoldings;
-use crate::utils::{scale_to_share, unix_time, Scale, ToBigDecimal};
+use crate::contracts::{
+    CurvePoolLpToken, CurveRegistry, Erc20Token, MulticallReader, SnapshotCalls, YearnRegistry,
+    YearnVaultV2,
+};
+use crate::error::NumbaError;
+use crate::oracle::{ChainlinkOracle, PriceOracle};
+use crate::types::{CoinHolding, SnapshotBlock, UserVaultHoldings, VaultPosition};
+use crate::utils::{scale_to_share, Scale, ToBigDecimal};
 
-pub async fn get_crvcomp_holdings(
-    provider: &Provider<Http>,
-    holder_address: &str,
-) -> Result<UserVaultHoldings> {
-    let me = Address::from_str(holder_address).expect("Holder address is invalid");
+/// How the Curve registry reports the coin balances of a pool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveBalances {
+    /// Balances of the coins the pool holds directly (e.g. aDAI, aSUSD)
+    Balances,
+    /// Balances of the underlying coins of lending pools (e.g. DAI and USDC behind cDAI, cUSDC)
+    UnderlyingBalances,
+}
 
-    let yearn_vault =
-        YearnVaultV2::new(&provider, YEARN_VAULT_V2_ABI, YEARN_CRV_COMP_VAULT_ADDRESS);
+impl CurveBalances {
+    pub fn read_call(
+        &self,
+        registry: &CurveRegistry,
+        pool: Address,
+    ) -> Result<ContractCall<Provider<Http>, Vec<Uint>>, NumbaError> {
+        match self {
+            CurveBalances::Balances => registry.get_balances_call(pool),
+            CurveBalances::UnderlyingBalances => registry.get_underlying_balances_call(pool),
+        }
+    }
 
-    let curve_registry = CurveRegistry::new(&provider, CURVE_REGISTRY_ABI, CURVE_REGISTRY_ADDRESS);
-    let curve_comp_lp_token = CurvePoolLpToken::new(
-        &provider,
-        CURVE_COMP_LP_TOKEN_ABI,
-        CURVE_COMP_LP_TOKEN_ADDRESS,
-    );
+    pub async fn coins(
+        &self,
+        registry: &CurveRegistry,
+        pool: Address,
+        block: BlockNumber,
+    ) -> Result<Vec<Address>, NumbaError> {
+        match self {
+            CurveBalances::Balances => registry.get_coins(pool, block).await,
+            CurveBalances::UnderlyingBalances => registry.get_underlying_coins(pool, block).await,
+        }
+    }
 
-    // Get the DAI+USDC holdings of the Curve Comp Pool
-    let balances = curve_registry.get_comp_dai_usdc().await?;
-    let dai_in_curve = balances.get(0).unwrap().to_big_dec();
-    let usdc_in_curve = balances.get(1).unwrap().to_big_dec();
+    /// The decimals of the balance `read_call` returns for a coin with `coin_decimals` decimals
+    fn balance_decimals(&self, coin_decimals: u8) -> u8 {
+        match self {
+            CurveBalances::Balances => coin_decimals,
+            // The registry normalizes the underlying balances of lending pools to 18 decimals
+            CurveBalances::UnderlyingBalances => 18,
+        }
+    }
 
-    // Get the total number of LP Tokens for that pool
-    let total_lp_tokens = &curve_comp_lp_token.total_supply().await?.to_big_dec();
+    /// Index into the result of `get_n_coins` that counts the coins we read balances for
+    fn n_coins_index(&self) -> usize {
+        match self {
+            CurveBalances::Balances => 0,
+            CurveBalances::UnderlyingBalances => 1,
+        }
+    }
+}
 
-    // Get the number of yearn vault shares that I own
-    let my_vault_shares = &yearn_vault.balance_of(me).await?.to_big_dec();
+/// A yearn vault whose want token is the LP token of a Curve pool
+pub trait VaultAdapter {
+    fn name(&self) -> &str;
 
-    // Get the price per vault share and scale it down by 1e18
-    let price_per_share = &yearn_vault
-        .get_price_per_share()
-        .await?
-        .to_big_dec()
-        .scale_1e18();
+    fn vault_address(&self) -> &str;
 
-    // Based on my vault shares and the price per share, calculate my number of LP tokens for the curve pool
-    let my_crv_lp_tokens = my_vault_shares * price_per_share;
+    fn balances(&self) -> CurveBalances;
 
-    // Scale the holdings of the Curve Pool down to the number of my LP tokens
-    let my_usdc = scale_to_share(&usdc_in_curve, &total_lp_tokens, &my_crv_lp_tokens);
-    let my_dai = scale_to_share(&dai_in_curve, &total_lp_tokens, &my_crv_lp_tokens);
+    /// The Chainlink USD feed of each coin, in the order of the coins of the pool
+    fn price_feeds(&self) -> &[&'static str];
+}
 
-    // Sum up USDC and DAI (and we assume both are equal to 1 USD)
-    let both = &my_usdc + &my_dai;
+pub struct CurveVault {
+    pub name: &'static str,
+    pub vault_address: &'static str,
+    pub balances: CurveBalances,
+    pub price_feeds: &'static [&'static str],
+}
 
-    Ok(UserVaultHoldings {
-        timestamp: unix_time(),
-        price_per_share: price_per_share.clone(),
-        usd_1: my_dai.scale_1e18(),
-        usd_2: my_usdc.scale_1e18(),
-        usd_all: both.scale_1e18(),
+impl CurveVault {
+    pub fn crv_comp() -> CurveVault {
+        CurveVault {
+            name: "crvCOMP",
+            vault_address: YEARN_CRV_COMP_VAULT_ADDRESS,
+            balances: CurveBalances::UnderlyingBalances,
+            // DAI and USDC behind cDAI and cUSDC
+            price_feeds: &[
+                CHAINLINK_DAI_USD_FEED_ADDRESS,
+                CHAINLINK_USDC_USD_FEED_ADDRESS,
+            ],
+        }
+    }
+
+    pub fn crv_saave() -> CurveVault {
+        CurveVault {
+            name: "crvSAAVE",
+            vault_address: YEARN_CRV_SAAVE_VAULT_ADDRESS,
+            balances: CurveBalances::Balances,
+            // aDAI and aSUSD are redeemable 1:1 for DAI and sUSD
+            price_feeds: &[
+                CHAINLINK_DAI_USD_FEED_ADDRESS,
+                CHAINLINK_SUSD_USD_FEED_ADDRESS,
+            ],
+        }
+    }
+}
+
+impl VaultAdapter for CurveVault {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn vault_address(&self) -> &str {
+        self.vault_address
+    }
+
+    fn balances(&self) -> CurveBalances {
+        self.balances
+    }
+
+    fn price_feeds(&self) -> &[&'static str] {
+        self.price_feeds
+    }
+}
+
+/// The vaults that are tracked by default
+pub fn default_vaults() -> Vec<Box<dyn VaultAdapter>> {
+    vec![
+        Box::new(CurveVault::crv_comp()),
+        Box::new(CurveVault::crv_saave()),
+    ]
+}
+
+/// The tracked vaults with the given names, in the order of the names
+pub fn vaults_by_name(names: &[String]) -> Vec<Box<dyn VaultAdapter>> {
+    let mut vaults = default_vaults();
+    let mut selected = vec![];
+    for name in names {
+        if let Some(index) = vaults.iter().position(|vault| vault.name() == name) {
+            selected.push(vaults.remove(index));
+        }
+    }
+    selected
+}
+
+/// The Curve addresses behind a yearn vault
+#[derive(Debug, Clone)]
+pub struct CurvePool {
+    pub lp_token: Address,
+    pub pool: Address,
+    pub coins: Vec<Address>,
+}
+
+/// The Curve pool of every vault resolved so far, keyed by the vault address. The addresses behind
+/// a vault never change.
+static CURVE_POOLS: Lazy<Mutex<HashMap<Address, CurvePool>>> = Lazy::new(Default::default);
+
+/// Resolve the Curve LP token, pool and coins of a vault, starting from the vault's `token()`.
+/// Only the first call for a vault reads from the chain.
+pub async fn resolve_curve_pool(
+    yearn_vault: &YearnVaultV2,
+    curve_registry: &CurveRegistry,
+    balances: CurveBalances,
+    block: BlockNumber,
+) -> Result<CurvePool> {
+    if let Some(curve_pool) = lock(&CURVE_POOLS).get(&yearn_vault.address()) {
+        return Ok(curve_pool.clone());
+    }
+
+    let lp_token = yearn_vault.token(block).await?;
+
+    let pool = curve_registry
+        .get_pool_from_lp_token(lp_token, block)
+        .await?;
+    if pool == Address::zero() {
+        return Err(NumbaError::UnexpectedResponse(format!(
+            "LP token {:?} is not registered in the Curve registry",
+            lp_token
+        ))
+        .into());
+    }
+
+    let registered_lp_token = curve_registry.get_lp_token(pool, block).await?;
+    if registered_lp_token != lp_token {
+        return Err(NumbaError::UnexpectedResponse(format!(
+            "Curve pool {:?} has LP token {:?} but the vault holds {:?}",
+            pool, registered_lp_token, lp_token
+        ))
+        .into());
+    }
+
+    let n_coins = curve_registry.get_n_coins(pool, block).await?;
+    let mut coins = balances.coins(curve_registry, pool, block).await?;
+    let n_coins = match n_coins.get(balances.n_coins_index()) {
+        Some(count) if !count.is_zero() && *count <= U256::from(coins.len()) => count.as_usize(),
+        _ => {
+            return Err(NumbaError::UnexpectedResponse(format!(
+                "Curve registry returned no valid coin count for pool {:?}: {:?}",
+                pool, n_coins
+            ))
+            .into())
+        }
+    };
+    coins.truncate(n_coins);
+
+    let curve_pool = CurvePool {
+        lp_token,
+        pool,
+        coins,
+    };
+    lock(&CURVE_POOLS).insert(yearn_vault.address(), curve_pool.clone());
+    Ok(curve_pool)
+}
+
+/// The symbol and decimals of an ERC-20 token
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Every token seen so far, neither the symbol nor the decimals of a token change
+static TOKEN_INFO: Lazy<Mutex<HashMap<Address, TokenInfo>>> = Lazy::new(Default::default);
+
+/// The ERC-20 `symbol()` and `decimals()` of a token, fetched once per token
+pub async fn token_info(
+    provider: &Provider<Http>,
+    token: Address,
+    block: BlockNumber,
+) -> Result<TokenInfo> {
+    if let Some(info) = lock(&TOKEN_INFO).get(&token) {
+        return Ok(info.clone());
+    }
+    let erc20 = Erc20Token::at(provider, ERC20_ABI, token)?;
+    let (symbol, decimals) = futures::try_join!(erc20.symbol(block), erc20.decimals(block))?;
+    let info = TokenInfo { symbol, decimals };
+    lock(&TOKEN_INFO).insert(token, info.clone());
+    Ok(info)
+}
+
+fn lock<T>(cache: &'static Mutex<T>) -> MutexGuard<'static, T> {
+    // The caches stay consistent even if another thread panicked while holding the lock
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn parse_holder_address(holder_address: &str) -> Result<Address, NumbaError> {
+    Address::from_str(holder_address).map_err(|_| {
+        NumbaError::InvalidAddress(format!("'{}' is not a hex address", holder_address))
     })
 }
 
-pub async fn get_crvsaave_holdings(
+/// Whether the vault contract exists at the block, it doesn't before its deployment
+pub async fn is_deployed(
     provider: &Provider<Http>,
+    vault: &dyn VaultAdapter,
+    snapshot_block: &SnapshotBlock,
+) -> Result<bool> {
+    let yearn_vault = YearnVaultV2::new(provider, YEARN_VAULT_V2_ABI, vault.vault_address())?;
+    let code = provider
+        .get_code(
+            yearn_vault.address(),
+            Some(BlockNumber::from(snapshot_block.number)),
+        )
+        .await
+        .map_err(NumbaError::from)?;
+    Ok(!code.0.is_empty())
+}
+
+pub async fn get_holdings(
+    provider: &Provider<Http>,
+    vault: &dyn VaultAdapter,
     holder_address: &str,
+    snapshot_block: &SnapshotBlock,
 ) -> Result<UserVaultHoldings> {
-    let me = Address::from_str(holder_address).expect("Holder address is invalid");
+    let me = parse_holder_address(holder_address)?;
+    let block = BlockNumber::from(snapshot_block.number);
+
+    let yearn_vault = YearnVaultV2::new(provider, YEARN_VAULT_V2_ABI, vault.vault_address())?;
 
-    let yearn_vault =
-        YearnVaultV2::new(&provider, YEARN_VAULT_V2_ABI, YEARN_CRV_SAAVE_VAULT_ADDRESS);
+    let curve_registry = CurveRegistry::new(provider, CURVE_REGISTRY_ABI, CURVE_REGISTRY_ADDRESS)?;
+    let curve_pool =
+        resolve_curve_pool(&yearn_vault, &curve_registry, vault.balances(), block).await?;
+    let curve_pool_lp_token = CurvePoolLpToken::at(provider, ERC20_ABI, curve_pool.lp_token)?;
 
-    let curve_registry = CurveRegistry::new(&provider, CURVE_REGISTRY_ABI, CURVE_REGISTRY_ADDRESS);
-    let curve_pool_lp_token = CurvePoolLpToken::new(
-        &provider,
-        CURVE_SAAVE_LP_TOKEN_ABI,
-        CURVE_SAAVE_LP_TOKEN_ADDRESS,
-    );
+    // Batch all reads of the snapshot into a single call against the same block
+    let reads = MulticallReader::new(provider, MULTICALL_ADDRESS)?
+        .read_snapshot(
+            SnapshotCalls {
+                price_per_share: yearn_vault.get_price_per_share_call()?,
+                vault_shares: yearn_vault.balance_of_call(me)?,
+                total_lp_tokens: curve_pool_lp_token.total_supply_call()?,
+                pool_balances: vault
+                    .balances()
+                    .read_call(&curve_registry, curve_pool.pool)?,
+            },
+            block,
+        )
+        .await?;
 
-    // Get the DAI+sUSD holdings of the Curve SAAVE Pool
-    let balances = curve_registry.get_saave_dai_susd().await?;
-    let dai_in_curve = balances.get(0).unwrap().to_big_dec();
-    let susd_in_curve = balances.get(1).unwrap().to_big_dec();
+    // The coin holdings of the Curve Pool
+    let balances = reads.pool_balances;
+    if balances.len() < curve_pool.coins.len() {
+        return Err(NumbaError::UnexpectedResponse(format!(
+            "Curve registry returned {} balances for the {} coins of pool {:?}",
+            balances.len(),
+            curve_pool.coins.len(),
+            curve_pool.pool
+        ))
+        .into());
+    }
 
-    // Get the total number of LP Tokens for that pool
-    let total_lp_tokens = &curve_pool_lp_token.total_supply().await?.to_big_dec();
+    // The total number of LP Tokens for that pool
+    let total_lp_tokens = &reads.total_lp_tokens.to_big_dec();
 
-    // Get the number of yearn vault shares that I own
-    let my_vault_shares = &yearn_vault.balance_of(me).await?.to_big_dec();
+    // The number of yearn vault shares that I own
+    let my_vault_shares = &reads.vault_shares.to_big_dec();
 
-    // Get the price per vault share and scale it down by 1e18
-    let price_per_share = &yearn_vault
-        .get_price_per_share()
+    // The vault shares and the price per share have the decimals of the vault
+    let vault_decimals = token_info(provider, yearn_vault.address(), block)
         .await?
+        .decimals;
+    let price_per_share = &reads
+        .price_per_share
         .to_big_dec()
-        .scale_1e18();
+        .scale_by_decimals(vault_decimals);
 
     // Based on my vault shares and the price per share, calculate my number of LP tokens for the curve pool
     let my_crv_lp_tokens = my_vault_shares * price_per_share;
 
-    // Scale the holdings of the Curve Pool down to the number of my LP tokens
-    let my_susd = scale_to_share(&susd_in_curve, &total_lp_tokens, &my_crv_lp_tokens);
-    let my_dai = scale_to_share(&dai_in_curve, &total_lp_tokens, &my_crv_lp_tokens);
+    // Price each coin on its own, stablecoins don't always trade at 1 USD
+    let prices = coin_prices(provider, vault, curve_pool.coins.len(), snapshot_block).await?;
 
-    // Sum up sUSD and DAI (and we assume both are equal to 1 USD)
-    let both = &my_susd + &my_dai;
+    let mut coins = vec![];
+    for ((coin, balance), price) in curve_pool.coins.iter().zip(&balances).zip(prices) {
+        let info = token_info(provider, *coin, block).await?;
+        // Scale the holdings of the Curve Pool down to the number of my LP tokens
+        let amount = scale_to_share(&balance.to_big_dec(), total_lp_tokens, &my_crv_lp_tokens)
+            .scale_by_decimals(vault.balances().balance_decimals(info.decimals));
+        coins.push(CoinHolding {
+            symbol: info.symbol,
+            usd: &amount * price,
+            amount,
+        });
+    }
+    let usd_all = coins.iter().map(|coin| &coin.usd).sum();
 
     Ok(UserVaultHoldings {
-        timestamp: unix_time(),
+        timestamp: snapshot_block.timestamp,
+        block_number: snapshot_block.number,
         price_per_share: price_per_share.clone(),
-        usd_1: my_dai.scale_1e18(),
-        usd_2: my_susd.scale_1e18(),
-        usd_all: both.scale_1e18(),
+        shares: Some(my_vault_shares.scale_by_decimals(vault_decimals)),
+        coins,
+        usd_all,
     })
 }
+
+/// The USD prices of the first `n_coins` coins of the vault's pool
+async fn coin_prices(
+    provider: &Provider<Http>,
+    vault: &dyn VaultAdapter,
+    n_coins: usize,
+    snapshot_block: &SnapshotBlock,
+) -> Result<Vec<BigDecimal>> {
+    if vault.price_feeds().len() < n_coins {
+        return Err(NumbaError::Config(format!(
+            "Vault {} has {} price feeds for {} coins",
+            vault.name(),
+            vault.price_feeds().len(),
+            n_coins
+        ))
+        .into());
+    }
+    let oracles = vault.price_feeds()[..n_coins]
+        .iter()
+        .map(|feed| ChainlinkOracle::new(provider, feed))
+        .collect::<Result<Vec<_>>>()?;
+    try_join_all(
+        oracles
+            .iter()
+            .map(|oracle| oracle.usd_price(snapshot_block)),
+    )
+    .await
+}
+
+/// Find every vault endorsed in the yearn registry in which the holder owns shares
+pub async fn discover_positions(
+    provider: &Provider<Http>,
+    holder_address: &str,
+    snapshot_block: &SnapshotBlock,
+) -> Result<Vec<VaultPosition>> {
+    let me = parse_holder_address(holder_address)?;
+    let block = BlockNumber::from(snapshot_block.number);
+
+    let yearn_registry = YearnRegistry::new(provider, YEARN_REGISTRY_ABI, YEARN_REGISTRY_ADDRESS)?;
+
+    let vaults = yearn_registry
+        .all_vaults(block)
+        .await?
+        .into_iter()
+        .map(|vault_address| YearnVaultV2::at(provider, YEARN_VAULT_V2_ABI, vault_address))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Read the share balance of every vault at once, most of them are empty
+    let balances = try_join_all(vaults.iter().map(|vault| vault.balance_of(me, block))).await?;
+
+    let held = vaults
+        .iter()
+        .zip(balances)
+        .filter(|(_, balance)| !balance.is_zero());
+    try_join_all(held.map(|(yearn_vault, balance)| async move {
+        let vault_address = yearn_vault.address();
+        let (info, price_per_share) =
+            futures::try_join!(token_info(provider, vault_address, block), async {
+                Ok(yearn_vault.get_price_per_share(block).await?)
+            })?;
+        Ok(VaultPosition {
+            vault_address,
+            symbol: info.symbol,
+            shares: balance.to_big_dec().scale_by_decimals(info.decimals),
+            price_per_share: price_per_share
+                .to_big_dec()
+                .scale_by_decimals(info.decimals),
+        })
+    }))
+    .await
+}